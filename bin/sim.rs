@@ -1,10 +1,11 @@
 extern crate image;
 
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
 use std::error::Error;
 use std::format;
 // Image processing library
 use std::process::Command;
+use std::time::Duration;
 
 use image::{ImageBuffer, Rgb};
 use indicatif::{ProgressBar, ProgressStyle};
@@ -12,9 +13,14 @@ use pathfinding::prelude::astar;
 use rand::seq::SliceRandom;
 use rand::Rng;
 use rusttype::{Font, Scale};
+use serde::{Deserialize, Serialize};
 
 use dpg::Grid;
-use dpg::{Actions, Block, BlockMap, Coords, Orientations, Robot, Size, World, RNG, XY};
+use dpg::{
+    junction_signals, render_grid_legend, rng_from_seed, Actions, BatteryModel, Block, BlockMap,
+    Budget, Checkpoint, Closure, Closures, CommitmentPolicy, Coords, CostKind, Orientations,
+    Payment, Robot, SignalControl, Signals, Size, Vehicle, World, RNG, XY,
+};
 
 const COLOR_RED: Rgb<u8> = image::Rgb([255, 0, 0]);
 const COLOR_GREEN: Rgb<u8> = image::Rgb([0, 255, 0]);
@@ -32,29 +38,20 @@ fn color_from_orientation(orientation: Orientations) -> Rgb<u8> {
 
 type ImageFormat = ImageBuffer<Rgb<u8>, Vec<u8>>;
 
+/// The background of the frames, with the lanes in their directions:
+/// without the robots, it loads back as the map with `grid_from_image`.
 fn visualize_map(world: &World) -> ImageFormat {
-    let size = world.size();
-    let mut imgbuf = image::ImageBuffer::new(size.x as u32, size.y as u32);
-    imgbuf.fill(0);
-
-    for (u, v, pixel) in imgbuf.enumerate_pixels_mut() {
-        let v2 = size.y - v as i16 - 1;
-        let xy = XY {
-            x: u as i16,
-            // y: (size.y - (u as i16)),
-            y: v2 as i16,
-        };
-        let cell = world.grid.get_cell(&xy);
-        let color = cell.color;
-        *pixel = color;
-    }
-    imgbuf
+    render_grid_legend(&world.grid)
 }
 
 fn visualize_robots(grid: &Grid, robots: &Vec<Robot>, imgbuf: &mut ImageFormat) {
     // create a png of the grid and robots
 
     for robot in robots {
+        for xy in &robot.trail {
+            let y = grid.size.y as u32 - xy.y as u32 - 1;
+            *imgbuf.get_pixel_mut(xy.x as u32, y) = robot.color;
+        }
         let xy = robot.xy();
         // let c = grid.get_cell(&xy);
         let color = robot.color;
@@ -134,7 +131,21 @@ fn random_update(
 /// Time of day in seconds
 type TimeOfDaySec = f64;
 
-#[derive(Debug)]
+/// A road closed every day between two times
+#[derive(Debug, Clone, Copy)]
+pub struct ScheduledClosure {
+    pub closure: Closure,
+    pub from: TimeOfDaySec,
+    pub to: TimeOfDaySec,
+}
+
+impl ScheduledClosure {
+    fn is_active(&self, time_of_day: TimeOfDaySec) -> bool {
+        self.from <= time_of_day && time_of_day < self.to
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Objectives {
     pub home: Coords,
     pub work: Coords,
@@ -142,17 +153,27 @@ pub struct Objectives {
     // pub work_end: TimeOfDaySec,
 }
 
+#[derive(Serialize, Deserialize)]
 pub enum SimpleAgentStates {
     TRAVELING_TO_WORK,
     TRAVELING_TO_HOME,
 }
 
+/// Below this fraction of the capacity, agents head to the closest free charger
+const LOW_BATTERY: f64 = 0.3;
+
+#[derive(Serialize, Deserialize)]
 pub struct SimpleAgent {
     pub objectives: Objectives,
     pub state: SimpleAgentStates,
     pub plan: Option<PlanResult>,
+    /// The charger the agent is heading to or charging at
+    pub charger: Option<Coords>,
+    /// Number of steps spent with a low battery and no free charger
+    pub charger_waits: usize,
 }
 
+#[derive(Serialize, Deserialize)]
 pub enum PlanResult {
     Success(VecDeque<Coords>),
     Failure,
@@ -161,7 +182,8 @@ pub enum PlanResult {
 impl SimpleAgent {
     fn replan(&mut self, start: &Coords, world: &World, goal: Coords) {
         // let start = robot.coords;
-        assert!(world.valid_coords(start));
+        // robots caught in a closure plan their way out of it
+        assert!(world.valid_coords(start) || world.closures.is_closed(start));
         let result = astar(
             start,
             |c| world.successors(c).into_iter().map(|p| (p, 1)),
@@ -192,7 +214,47 @@ impl SimpleAgent {
         }
     }
 
+    /// Drops the path if it crosses a closed road; returns whether it did
+    fn reroute_around(&mut self, closures: &Closures) -> bool {
+        match &self.plan {
+            Some(PlanResult::Success(path)) if closures.crosses(path) => {
+                self.plan = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Plans again if no path was found before, e.g. once a road reopens
+    fn retry_if_failed(&mut self) {
+        if let Some(PlanResult::Failure) = self.plan {
+            self.plan = None;
+        }
+    }
+
+    fn needs_charger(&self, robot: &Robot, model: &BatteryModel) -> bool {
+        self.charger.is_none() && (robot.battery as f64) < LOW_BATTERY * model.capacity as f64
+    }
+
+    /// Heads to the closest charger that no other agent chose, if there is one
+    fn seek_charger(&mut self, robot: &Robot, chargers: &[Coords], taken: &HashSet<Coords>) {
+        let closest = chargers
+            .iter()
+            .filter(|c| !taken.contains(c))
+            .min_by_key(|c| c.dist(&robot.coords));
+        match closest {
+            Some(charger) => {
+                self.charger = Some(*charger);
+                self.plan = None;
+            }
+            None => self.charger_waits += 1,
+        }
+    }
+
     fn get_goal(&self) -> Coords {
+        if let Some(charger) = self.charger {
+            return charger;
+        }
         match self.state {
             SimpleAgentStates::TRAVELING_TO_WORK => self.objectives.work,
             SimpleAgentStates::TRAVELING_TO_HOME => self.objectives.home,
@@ -207,6 +269,16 @@ impl SimpleAgent {
         world: &World,
         horizon: usize,
     ) -> Vec<Actions> {
+        if let Some(charger) = self.charger {
+            if robot.coords == charger {
+                if robot.battery < world.battery.map_or(0, |b| b.capacity) {
+                    return vec![Actions::Wait; horizon];
+                }
+                // charged: back to the objectives
+                self.charger = None;
+                self.plan = None;
+            }
+        }
         match self.state {
             SimpleAgentStates::TRAVELING_TO_WORK => {
                 if robot.coords == self.objectives.work {
@@ -247,8 +319,14 @@ impl SimpleAgent {
                         self.objectives, robot.coords
                     );
                 }
-                if path.len() >= 2 && path[1] == robot.coords {
-                    path.pop_front();
+                // fast vehicles may have gone through several cells of the path
+                if let Some(k) = path
+                    .iter()
+                    .skip(1)
+                    .take(robot.vehicle.max_speed as usize)
+                    .position(|c| *c == robot.coords)
+                {
+                    path.drain(..=k);
                     // eprintln!("path is 1.\n objs: {:?}\n @ {:?}",
                     //           self.objectives, robot.coords);
                 }
@@ -279,15 +357,211 @@ impl SimpleAgent {
     }
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let mut rng: RNG = rand::thread_rng();
+/// Command line options of the simulator
+#[derive(Debug, Default)]
+pub struct SimArgs {
+    /// Charge VCG payments for every arbitration and report them at the end
+    pub vcg: bool,
+    /// Check the chosen solutions for Nash stability and report how many were not
+    pub stability: bool,
+    /// Compare the heuristic with the exact front on games up to this many players
+    pub compare_exact: Option<usize>,
+    /// Keep the previous step's order: "honor" or a tolerance on the total cost
+    pub commitment: CommitmentPolicy,
+    /// The costs the fronts are computed on: "waits", "arrival" or "stop-and-go"
+    pub cost_model: CostKind,
+    /// Maximum number of orders evaluated per game and step
+    pub budget_nodes: Option<usize>,
+    /// Maximum time spent per game and step, in milliseconds
+    pub budget_ms: Option<u64>,
+    /// Reuse the results of games equal up to translation and symmetry
+    pub cache: bool,
+    /// Let the leaders plan first in the games they are part of
+    pub stackelberg: bool,
+    /// Number of robots (the first ones placed) in the leader class
+    pub leaders: usize,
+    /// Print why this robot waits, whenever it does
+    pub explain_robot: Option<usize>,
+    /// Order block by block the games with more players than this
+    pub hierarchical: Option<usize>,
+    /// Seed of the random generator; two runs with the same seed are identical
+    pub seed: Option<u64>,
+    /// Save a checkpoint every this many steps
+    pub checkpoint_every: Option<usize>,
+    /// Continue the run saved in this checkpoint, with its configuration;
+    /// with `--seed`, the generator is reseeded to branch off a different run
+    pub restore: Option<String>,
+    /// Signals at the junctions: "fixed:GREEN" or "actuated:MIN:MAX", in steps
+    pub signals: Option<(SignalControl, usize)>,
+    /// Battery capacity of the robots, in moves; without it batteries are ignored
+    pub battery: Option<u32>,
+    /// Number of parking spots turned into charging spots (by default one in ten)
+    pub chargers: Option<usize>,
+    /// Mix of vehicles, with their weights, e.g. "bus:1,scooter:2,car:7"; by default all cars
+    pub vehicles: Vec<(Vehicle, u32)>,
+    /// Roads closed by time of day, e.g. "00:01-00:03@40,20" or "00:01:30-00:03@40,20,E"
+    pub closures: Vec<ScheduledClosure>,
+}
+
+impl SimArgs {
+    pub fn parse() -> Result<Self, String> {
+        let mut args = SimArgs::default();
+        let mut it = std::env::args().skip(1);
+        while let Some(arg) = it.next() {
+            match arg.as_str() {
+                "--vcg" => args.vcg = true,
+                "--stability" => args.stability = true,
+                "--compare-exact" => args.compare_exact = Some(parse_value(&arg, it.next())?),
+                "--cache" => args.cache = true,
+                "--stackelberg" => args.stackelberg = true,
+                "--leaders" => args.leaders = parse_value(&arg, it.next())?,
+                "--explain-robot" => args.explain_robot = Some(parse_value(&arg, it.next())?),
+                "--hierarchical" => args.hierarchical = Some(parse_value(&arg, it.next())?),
+                "--seed" => args.seed = Some(parse_value(&arg, it.next())?),
+                "--checkpoint-every" => args.checkpoint_every = Some(parse_value(&arg, it.next())?),
+                "--restore" => args.restore = Some(parse_value(&arg, it.next())?),
+                "--budget-nodes" => args.budget_nodes = Some(parse_value(&arg, it.next())?),
+                "--budget-ms" => args.budget_ms = Some(parse_value(&arg, it.next())?),
+                "--battery" => args.battery = Some(parse_value(&arg, it.next())?),
+                "--chargers" => args.chargers = Some(parse_value(&arg, it.next())?),
+                "--vehicles" => {
+                    let value: String = parse_value(&arg, it.next())?;
+                    args.vehicles = parse_vehicles(&value)?;
+                }
+                "--closure" => {
+                    let value: String = parse_value(&arg, it.next())?;
+                    args.closures.push(parse_closure(&value)?);
+                }
+                "--signals" => {
+                    let value: String = parse_value(&arg, it.next())?;
+                    args.signals = Some(parse_signals(&value)?);
+                }
+                "--commit" => {
+                    let value: String = parse_value(&arg, it.next())?;
+                    args.commitment = if value == "honor" {
+                        CommitmentPolicy::Honor
+                    } else {
+                        let tolerance = parse_value(&arg, Some(value))?;
+                        CommitmentPolicy::HonorWithin { tolerance }
+                    };
+                }
+                "--cost" => {
+                    let value: String = parse_value(&arg, it.next())?;
+                    args.cost_model = CostKind::from_name(&value)
+                        .ok_or(format!("invalid value {value:?} for {arg}"))?;
+                }
+                _ => return Err(format!("unknown argument {arg:?}")),
+            }
+        }
+        Ok(args)
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(arg: &str, value: Option<String>) -> Result<T, String> {
+    let value = value.ok_or(format!("missing value for {arg}"))?;
+    value
+        .parse()
+        .map_err(|_| format!("invalid value {value:?} for {arg}"))
+}
+
+fn parse_signals(value: &str) -> Result<(SignalControl, usize), String> {
+    let invalid = || format!("invalid value {value:?} for --signals");
+    let steps = |s: &str| s.parse::<usize>().ok().filter(|n| *n > 0).ok_or_else(invalid);
+    match value.split(':').collect::<Vec<_>>().as_slice() {
+        ["fixed", green] => Ok((SignalControl::FixedTime, steps(green)?)),
+        ["actuated", min_green, max_green] => {
+            let min_green = steps(min_green)?;
+            let max_green = steps(max_green)?;
+            if min_green > max_green {
+                return Err(invalid());
+            }
+            let control = SignalControl::Actuated {
+                min_green,
+                max_green,
+            };
+            Ok((control, min_green))
+        }
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_vehicles(value: &str) -> Result<Vec<(Vehicle, u32)>, String> {
+    let invalid = || format!("invalid value {value:?} for --vehicles");
+    let mix = value
+        .split(',')
+        .map(|item| {
+            let (name, weight) = item.split_once(':').ok_or_else(invalid)?;
+            let vehicle = Vehicle::from_name(name).ok_or_else(invalid)?;
+            let weight = weight.parse::<u32>().map_err(|_| invalid())?;
+            Ok((vehicle, weight))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    if mix.iter().all(|(_, weight)| *weight == 0) {
+        return Err(invalid());
+    }
+    Ok(mix)
+}
+
+/// "HH:MM" or "HH:MM:SS"
+fn parse_time_of_day(value: &str) -> Option<TimeOfDaySec> {
+    let parts = value
+        .split(':')
+        .map(|p| p.parse::<u32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    let (h, m, s) = match parts.as_slice() {
+        [h, m] => (*h, *m, 0),
+        [h, m, s] => (*h, *m, *s),
+        _ => return None,
+    };
+    if h > 24 || m > 59 || s > 59 {
+        return None;
+    }
+    Some((h * 3600 + m * 60 + s) as TimeOfDaySec)
+}
+
+/// "FROM-TO@X,Y" closes the cell, "FROM-TO@X,Y,O" only the direction O (N, S, W or E)
+fn parse_closure(value: &str) -> Result<ScheduledClosure, String> {
+    let invalid = || format!("invalid value {value:?} for --closure");
+    let (times, place) = value.split_once('@').ok_or_else(invalid)?;
+    let (from, to) = times.split_once('-').ok_or_else(invalid)?;
+    let from = parse_time_of_day(from).ok_or_else(invalid)?;
+    let to = parse_time_of_day(to).ok_or_else(invalid)?;
+    if from >= to {
+        return Err(invalid());
+    }
+    let coord = |s: &str| s.parse::<i16>().map_err(|_| invalid());
+    let closure = match place.split(',').collect::<Vec<_>>().as_slice() {
+        [x, y] => Closure::cell(XY::new(coord(x)?, coord(y)?)),
+        [x, y, o] => {
+            let orientation = match *o {
+                "N" => Orientations::NORTH,
+                "S" => Orientations::SOUTH,
+                "W" => Orientations::WEST,
+                "E" => Orientations::EAST,
+                _ => return Err(invalid()),
+            };
+            Closure::lane(Coords::from(XY::new(coord(x)?, coord(y)?), orientation))
+        }
+        _ => return Err(invalid()),
+    };
+    Ok(ScheduledClosure { closure, from, to })
+}
+
+fn report_payments(world: &World) {
+    let total: Payment = world.payments.iter().sum();
+    eprintln!("VCG payments: total {total}");
+    let mut by_robot: Vec<(usize, Payment)> = world.payments.iter().copied().enumerate().collect();
+    by_robot.sort_by_key(|(_, p)| -*p);
+    for (robot_name, payment) in by_robot.iter().take(10) {
+        eprintln!("  robot {robot_name:5}: {payment}");
+    }
+}
+
+/// Builds the map, places the robots and gives each of them an agent
+fn new_simulation(args: &SimArgs, rng: &mut RNG) -> (World, Vec<SimpleAgent>) {
     let sx = 2;
     let sy = 2;
 
-    // let s = 1;
-    let ndays = 1.0;
-    let ndays = 1.0 / 24.0 / 10.0;
-
     let border = 1;
     let map_size = Size::new(border*2 + sx, border*2 + sy);
     let block_size = Size::new(16, 16);
@@ -304,15 +578,25 @@ fn main() -> Result<(), Box<dyn Error>> {
             continue;
         } else {
             let b = if rng.gen_bool(prob_parking) {
-                Block::with_parking(block_size, parking_interval, &mut rng)
+                Block::with_parking(block_size, parking_interval, rng)
             } else {
-                Block::basic_with_roads(block_size, &mut rng)
+                Block::basic_with_roads(block_size, rng)
             };
             bl.set_block(p, b);
         }
     }
 
-    let g = bl.stitch();
+    let mut g = bl.stitch(rng);
+    if args.battery.is_some() {
+        let spots = g.empty_parking_cells.queue.clone().into_sorted_vec();
+        let nchargers = args.chargers.unwrap_or(spots.len().div_ceil(10));
+        for k in 0..nchargers.min(spots.len()) {
+            let xy = spots[k * spots.len() / nchargers];
+            let orientation = g.get_cell(&xy).random_direction(rng);
+            g.make_charging_cell(&Coords { xy, orientation });
+        }
+        eprintln!("{nchargers} charging spaces");
+    }
     let mut nparkings = 0;
     for (_, cell) in g.iterate_cells() {
         if cell.is_parking {
@@ -325,14 +609,33 @@ fn main() -> Result<(), Box<dyn Error>> {
     if nrobots == 0 {
         panic!("No robots");
     }
-    let speed_km_h = 30.0;
-    let speed_m_s = speed_km_h * 1000.0 / 3600.0;
-    let size_cell_m = 5.0;
-    let sim_step_secs = size_cell_m / speed_m_s;
-    eprintln!("Simulation step size: {} seconds", sim_step_secs);
-    let steps = (ndays * 24.0 * 60.0 * 60.0 / sim_step_secs) as usize;
-
     let mut world = World::new(g);
+    world.config.vcg_payments = args.vcg;
+    world.config.check_stability = args.stability;
+    world.config.compare_exact = args.compare_exact;
+    world.config.commitment = args.commitment;
+    world.config.cost_model = args.cost_model;
+    world.config.use_cache = args.cache;
+    world.config.stackelberg = args.stackelberg;
+    world.config.explain_robot = args.explain_robot;
+    world.config.hierarchical_threshold = args.hierarchical;
+    if args.budget_nodes.is_some() || args.budget_ms.is_some() {
+        world.config.budget = Budget {
+            nodes: args.budget_nodes,
+            time: args.budget_ms.map(Duration::from_millis),
+        };
+    }
+
+    if let Some((control, green)) = args.signals {
+        for signal in junction_signals(&world.grid, control, green) {
+            world.signals.add(signal);
+        }
+        eprintln!("{} signalized junctions", world.signals.len());
+    }
+
+    if let Some(capacity) = args.battery {
+        world.set_battery_model(BatteryModel::new(capacity));
+    }
 
     eprintln!("Robot placement: {nrobots} robots");
     // let mut use_coords = Vec::new();
@@ -350,14 +653,14 @@ fn main() -> Result<(), Box<dyn Error>> {
         let xy_work = ordered[ordered.len() - 1 - i];
 
         let cell_home = world.grid.get_cell(&xy_home);
-        let orientation_home = cell_home.random_direction(&mut rng);
+        let orientation_home = cell_home.random_direction(rng);
         let coords_home = Coords {
             xy: xy_home,
             orientation: orientation_home,
         };
 
         let cell_work = world.grid.get_cell(&xy_work);
-        let orientation_work = cell_work.random_direction(&mut rng);
+        let orientation_work = cell_work.random_direction(rng);
         let coords_work = Coords {
             xy: xy_work,
             orientation: orientation_work,
@@ -371,6 +674,8 @@ fn main() -> Result<(), Box<dyn Error>> {
             objectives: objs,
             state: SimpleAgentStates::TRAVELING_TO_WORK,
             plan: None,
+            charger: None,
+            charger_waits: 0,
         };
 
         if i % 10 == 0 {
@@ -379,7 +684,15 @@ fn main() -> Result<(), Box<dyn Error>> {
 
         agents.push(agent);
 
-        world.place_robot(coords_home);
+        let robot_name = if args.vehicles.is_empty() {
+            world.place_robot(coords_home)
+        } else {
+            let (vehicle, _) = args.vehicles.choose_weighted(rng, |(_, w)| *w).unwrap();
+            world.place_vehicle(coords_home, *vehicle)
+        };
+        if i < args.leaders {
+            world.set_priority(robot_name, 1);
+        }
         //
         // let coords = Coords { xy: xy_home, orientation: orientation_home };
         // use_coords.push(coords);
@@ -388,8 +701,49 @@ fn main() -> Result<(), Box<dyn Error>> {
     //     world.place_robot(coords);
     // }
 
+    (world, agents)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = SimArgs::parse()?;
+
+    // let s = 1;
+    let ndays = 1.0;
+    let ndays = 1.0 / 24.0 / 10.0;
+
+    let speed_km_h = 30.0;
+    let speed_m_s = speed_km_h * 1000.0 / 3600.0;
+    let size_cell_m = 5.0;
+    let sim_step_secs = size_cell_m / speed_m_s;
+    eprintln!("Simulation step size: {} seconds", sim_step_secs);
+    let steps = (ndays * 24.0 * 60.0 * 60.0 / sim_step_secs) as usize;
+
+    let mut c = match &args.restore {
+        Some(filename) => {
+            let mut c: Checkpoint<SimpleAgent> = Checkpoint::load(filename)?;
+            eprintln!("Restored {filename} at step {}", c.step);
+            if let Some(seed) = args.seed {
+                eprintln!("Random seed: {seed}");
+                c.rng = rng_from_seed(seed);
+            }
+            c
+        }
+        None => {
+            let seed = args.seed.unwrap_or_else(|| rand::thread_rng().gen());
+            eprintln!("Random seed: {seed}");
+            let mut rng: RNG = rng_from_seed(seed);
+            let (world, agents) = new_simulation(&args, &mut rng);
+            Checkpoint {
+                step: 0,
+                world,
+                rng,
+                agents,
+            }
+        }
+    };
+
     let mut states: Vec<Vec<Robot>> = Vec::new();
-    states.push(world.robots.clone());
+    states.push(c.world.robots.clone());
     eprintln!("Simulation of {steps} steps");
 
     let pb = ProgressBar::new(steps as u64);
@@ -401,22 +755,160 @@ fn main() -> Result<(), Box<dyn Error>> {
         .progress_chars("##-"),
     );
 
-    {
-        let world2 = world.clone();
+    // the planners only look at the map, which changes only with the closures applied to both;
+    // the signals are obeyed when the plans are executed
+    let mut world2 = c.world.clone();
+    world2.signals = Signals::default();
+    let chargers = world2.charging_cells();
+    for s in &args.closures {
+        let size = world2.grid.size;
+        if !size.in_bounds(s.closure.xy) {
+            return Err(format!("closure at {:?} outside the map {size:?}", s.closure.xy).into());
+        }
+    }
+    let mut closures_active = vec![false; args.closures.len()];
+    let mut rerouted = 0;
+    let first_step = c.step;
+    for i in first_step..steps {
+        if i % 5 == 0 {
+            pb.set_position(i as u64);
+        }
+        let time_of_day = i as f64 * sim_step_secs;
+        let mut changed = false;
+        for (s, active) in args.closures.iter().zip(closures_active.iter_mut()) {
+            if s.is_active(time_of_day) != *active {
+                *active = !*active;
+                changed = true;
+                if !*active {
+                    c.world.reopen(s.closure);
+                    world2.reopen(s.closure);
+                    c.agents.iter_mut().for_each(|a| a.retry_if_failed());
+                }
+            }
+        }
+        if changed {
+            // overlapping closures stay closed until the last one ends
+            let mut closed = Vec::new();
+            for (s, _) in args.closures.iter().zip(&closures_active).filter(|(_, a)| **a) {
+                closed.extend(c.world.close(s.closure));
+                world2.close(s.closure);
+            }
+            if !closed.is_empty() {
+                for agent in c.agents.iter_mut() {
+                    if agent.reroute_around(&world2.closures) {
+                        rerouted += 1;
+                    }
+                }
+            }
+        }
+        let agents = &mut c.agents;
         let mut robot_update_function =
-            move |rng: &mut RNG, robot_name: usize, robot: &Robot, horizon: usize| {
+            |rng: &mut RNG, robot_name: usize, robot: &Robot, horizon: usize| {
+                if let Some(model) = &world2.battery {
+                    if agents[robot_name].needs_charger(robot, model) {
+                        let taken: HashSet<Coords> =
+                            agents.iter().filter_map(|a| a.charger).collect();
+                        agents[robot_name].seek_charger(robot, &chargers, &taken);
+                    }
+                }
                 agents[robot_name].update(rng, robot_name, robot, &world2, horizon)
             };
-        for i in 0..steps {
-            if i % 5 == 0 {
-                pb.set_position(i as u64);
-            }
-            world.step_robots(&mut robot_update_function, &mut rng);
-            states.push(world.robots.clone());
+        c.world.step_robots(&mut robot_update_function, &mut c.rng);
+        states.push(c.world.robots.clone());
+        c.step = i + 1;
+        if args.checkpoint_every.is_some_and(|n| c.step % n == 0) {
+            let filename = format!("checkpoint-{}.bin", c.step);
+            c.save(&filename)?;
         }
     }
     pb.finish();
     eprintln!("Simulation done.");
+    let world = c.world;
+    if args.vcg {
+        report_payments(&world);
+    }
+    if args.stability {
+        let stats = &world.stats;
+        eprintln!(
+            "Nash stability: {} of {} chosen solutions were unstable",
+            stats.unstable, stats.stability_checked
+        );
+    }
+    {
+        let stats = &world.stats;
+        eprintln!(
+            "Arbitration: {} games, {} searches cut short by the budget",
+            stats.games, stats.incomplete_searches
+        );
+        eprintln!(
+            "Order reversals: {} of {} pairs ordered at consecutive steps ({} commitments kept)",
+            stats.order_reversals, stats.order_pairs, stats.commitments_kept
+        );
+    }
+    if world.battery.is_some() {
+        let stats = &world.stats;
+        let waits: usize = c.agents.iter().map(|a| a.charger_waits).sum();
+        eprintln!(
+            "Battery: {} robot-steps charging, {} waiting for a free charger, \
+             {} batteries ran out ({} robot-steps stranded)",
+            stats.charging_steps, waits, stats.strandings, stats.stranded_steps
+        );
+    }
+    if !world.signals.is_empty() {
+        eprintln!(
+            "Signals: {} junctions, {} plans stopped at a red light",
+            world.signals.len(),
+            world.stats.signal_stops
+        );
+    }
+    if !args.closures.is_empty() {
+        eprintln!(
+            "Closures: {} scheduled, {} agents rerouted, {} plans stopped at a closed road",
+            args.closures.len(),
+            rerouted,
+            world.stats.closure_stops
+        );
+    }
+    if args.cache {
+        let cache = &world.cache;
+        eprintln!(
+            "Cache: {} games stored, {} hits, {} misses, hit rate {:.1}%",
+            cache.len(),
+            cache.hits,
+            cache.misses,
+            100.0 * cache.hit_rate()
+        );
+    }
+    if args.hierarchical.is_some() {
+        let stats = &world.stats;
+        eprintln!(
+            "Hierarchical: {} games ordered by block, {} fell back to the full search",
+            stats.hierarchical_games, stats.hierarchical_fallbacks
+        );
+    }
+    if args.stackelberg {
+        let stats = &world.stats;
+        let mean_gain = if stats.stackelberg_games == 0 {
+            0.0
+        } else {
+            stats.leader_gain / stats.stackelberg_games as f64
+        };
+        eprintln!(
+            "Stackelberg: {} games with leaders, leaders wait {:.2} steps less on average",
+            stats.stackelberg_games, mean_gain
+        );
+    }
+    if args.compare_exact.is_some() {
+        let stats = &world.stats;
+        eprintln!(
+            "Exact comparison: {} games, heuristic front incomplete in {}, \
+             {} front points missed, total social cost gap {}",
+            stats.exact_compared,
+            stats.exact_incomplete,
+            stats.exact_missed_points,
+            stats.exact_social_cost_gap
+        );
+    }
 
     let do_movie = true;
 
@@ -442,6 +934,7 @@ fn main() -> Result<(), Box<dyn Error>> {
             if a % SKIP_FRAMES_VIDEO != 0 {
                 continue;
             }
+            let a = first_step + a;
             let time_of_day = a as f64 * sim_step_secs;
 
             // convert to hours and minutes
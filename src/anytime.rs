@@ -0,0 +1,177 @@
+use std::iter::Peekable;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+use itertools::{Itertools, Permutations};
+use serde::{Deserialize, Serialize};
+
+use crate::{assign, evaluate, ArbResult, ArbSetup, CostModel, WaitCount};
+
+/// Number of orders evaluated by the searches that cannot stop on time when the budget
+/// has no node limit
+pub const DEFAULT_MAX_NODES: usize = 100000;
+
+/// Limits on how much work an arbitration step may do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Budget {
+    /// Maximum number of orders to evaluate
+    pub nodes: Option<usize>,
+    /// Maximum wall-clock time
+    pub time: Option<Duration>,
+}
+
+impl Budget {
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+    pub fn nodes(n: usize) -> Self {
+        Self {
+            nodes: Some(n),
+            time: None,
+        }
+    }
+    pub fn time(d: Duration) -> Self {
+        Self {
+            nodes: None,
+            time: Some(d),
+        }
+    }
+    /// The number of orders for the searches that only take a number of orders
+    pub fn max_nodes(&self) -> usize {
+        self.nodes.unwrap_or(DEFAULT_MAX_NODES)
+    }
+}
+
+/// Resumable search over the priority orders of a game.
+///
+/// The orders are visited varying the first positions first, like `get_permutations`,
+/// so that a budget of k! nodes explores the same orders as `find_feasible_plans(s, k!)`.
+/// The front is computed for the cost model, the waits by default.
+pub struct AnytimeArbitration<M: CostModel = WaitCount> {
+    setup: ArbSetup,
+    model: M,
+    perms: Peekable<Permutations<Range<usize>>>,
+    /// The best front found so far
+    pub result: ArbResult<M::Value>,
+    /// Number of orders evaluated so far
+    pub explored: usize,
+    /// Whether all the orders were evaluated
+    pub complete: bool,
+}
+
+impl AnytimeArbitration {
+    pub fn new(s: &ArbSetup) -> Self {
+        Self::with_model(s, WaitCount)
+    }
+}
+
+impl<M: CostModel> AnytimeArbitration<M> {
+    pub fn with_model(s: &ArbSetup, model: M) -> Self {
+        let n = s.agents.len();
+        Self {
+            setup: s.clone(),
+            model,
+            perms: (0..n).permutations(n).peekable(),
+            result: ArbResult::empty(),
+            explored: 0,
+            complete: false,
+        }
+    }
+
+    /// Continues the search until the budget is used up; returns whether it is complete.
+    pub fn run(&mut self, budget: Budget) -> bool {
+        let start = Instant::now();
+        let n = self.setup.agents.len();
+        let mut nodes = 0;
+        loop {
+            if self.perms.peek().is_none() {
+                self.complete = true;
+                break;
+            }
+            if budget.nodes.is_some_and(|max| nodes >= max) {
+                break;
+            }
+            if budget.time.is_some_and(|max| start.elapsed() >= max) {
+                break;
+            }
+            let p = self.perms.next().unwrap();
+            let perm = (0..n).map(|i| n - 1 - p[n - 1 - i]).collect_vec();
+            if let Some((_, solution)) = assign(&self.setup, &perm) {
+                let costs = evaluate(&self.model, &self.setup, &solution);
+                self.result.add(costs, &solution);
+            }
+            nodes += 1;
+            self.explored += 1;
+        }
+        self.complete
+    }
+}
+
+/// Runs the anytime search once; returns the front found and whether it is complete.
+pub fn find_feasible_plans_anytime(s: &ArbSetup, budget: Budget) -> (ArbResult, bool) {
+    find_feasible_plans_anytime_with(s, budget, WaitCount)
+}
+
+/// Like `find_feasible_plans_anytime`, with the front computed for the cost model.
+pub fn find_feasible_plans_anytime_with<M: CostModel>(
+    s: &ArbSetup,
+    budget: Budget,
+    model: M,
+) -> (ArbResult<M::Value>, bool) {
+    let mut search = AnytimeArbitration::with_model(s, model);
+    let complete = search.run(budget);
+    (search.result, complete)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::*;
+
+    fn crossing() -> ArbSetup {
+        let agents = vec![
+            ArbAgent::new(
+                Coords::from(XYCell::new(1, 0), Orientations::WEST),
+                vec![Actions::Forward; 3],
+            ),
+            ArbAgent::new(
+                Coords::from(XYCell::new(0, -2), Orientations::NORTH),
+                vec![Actions::Forward; 3],
+            ),
+            ArbAgent::new(
+                Coords::from(XYCell::new(-2, -1), Orientations::EAST),
+                vec![Actions::Forward; 3],
+            ),
+        ];
+        ArbSetup { agents }
+    }
+
+    #[test]
+    fn test_anytime_resume() {
+        let setup = crossing();
+        let mut search = AnytimeArbitration::new(&setup);
+        assert!(!search.run(Budget::nodes(2)));
+        assert_eq!(search.explored, 2);
+        assert!(!search.run(Budget::nodes(3)));
+        assert!(search.run(Budget::nodes(1)));
+        assert_eq!(search.explored, 6);
+        assert_eq!(search.result, find_feasible_plans(&setup, 0));
+    }
+
+    #[test]
+    fn test_anytime_same_orders_as_permutations() {
+        let setup = crossing();
+        let (result, complete) = find_feasible_plans_anytime(&setup, Budget::nodes(2));
+        assert!(!complete);
+        // 2! < 3: the first two agents are permuted
+        assert_eq!(result, find_feasible_plans(&setup, 3));
+    }
+
+    #[test]
+    fn test_time_budget_keeps_node_cap() {
+        let budget = Budget::time(Duration::from_millis(10));
+        assert_eq!(budget.max_nodes(), DEFAULT_MAX_NODES);
+        assert_eq!(Budget::nodes(7).max_nodes(), 7);
+    }
+}
@@ -0,0 +1,408 @@
+use std::collections::HashMap;
+
+use itertools::Itertools;
+
+use crate::{Actions, ArbAgent, ArbSetup, Coords, Orientations, PlanEnd, XYCell};
+
+/// Marks the origin (0, 0) in a scenario diagram; otherwise it is the bottom-left cell.
+pub const GLYPH_ORIGIN: char = '+';
+/// Gives the coordinates of the bottom-left cell, for when an agent sits on the origin
+pub const ORIGIN_LINE: &str = "+";
+pub const GLYPH_EMPTY: char = '.';
+
+pub fn orientation_from_glyph(c: char) -> Option<Orientations> {
+    match c {
+        '^' => Some(Orientations::NORTH),
+        'v' => Some(Orientations::SOUTH),
+        '<' => Some(Orientations::WEST),
+        '>' => Some(Orientations::EAST),
+        _ => None,
+    }
+}
+
+pub fn glyph_from_orientation(o: Orientations) -> char {
+    match o {
+        Orientations::NORTH => '^',
+        Orientations::SOUTH => 'v',
+        Orientations::WEST => '<',
+        Orientations::EAST => '>',
+    }
+}
+
+pub fn action_from_char(c: char) -> Option<Actions> {
+    match c {
+        'W' => Some(Actions::Wait),
+        'F' => Some(Actions::Forward),
+        'L' => Some(Actions::TurnLeft),
+        'R' => Some(Actions::TurnRight),
+        'B' => Some(Actions::Backward),
+        _ => None,
+    }
+}
+
+/// Parses a scenario: a grid diagram followed by the plans.
+///
+/// ```text
+/// . v . .
+/// . . + <
+/// > . . .
+/// . . ^ .
+/// *: FFF
+/// 1: FRF
+/// ```
+///
+/// Arrows are agents with their heading, numbered in reading order; spaces are ignored.
+/// A plan line `i: ...` gives the plan of agent `i` and `*: ...` the plan of the others.
+/// The actions may be followed by `stay` or `disappear` for the end of the plan
+/// and by `priority=N` for the priority class.
+/// Instead of the `+` glyph, a line `+: x y` can give the coordinates of the bottom-left cell.
+/// Empty lines and lines starting with `//` are skipped.
+pub fn parse_scenario(text: &str) -> Result<ArbSetup, String> {
+    let mut rows: Vec<Vec<char>> = Vec::new();
+    let mut plans: HashMap<usize, ArbAgent> = HashMap::new();
+    let mut default_plan = None;
+    let mut bottom_left = None;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+        if let Some((who, rest)) = line.split_once(':') {
+            if who.trim() == ORIGIN_LINE {
+                bottom_left = Some(parse_xy(rest).ok_or(format!("invalid origin in {line:?}"))?);
+                continue;
+            }
+            let plan = parse_plan(rest).map_err(|e| format!("{e} in {line:?}"))?;
+            match who.trim() {
+                "*" => default_plan = Some(plan),
+                i => {
+                    let i = i
+                        .parse()
+                        .map_err(|_| format!("invalid agent {i:?} in {line:?}"))?;
+                    plans.insert(i, plan);
+                }
+            }
+        } else if plans.is_empty() && default_plan.is_none() {
+            rows.push(line.chars().filter(|c| !c.is_whitespace()).collect_vec());
+        } else {
+            return Err(format!("grid line {line:?} after the plans"));
+        }
+    }
+    let nrows = rows.len() as i16;
+    let mut origin = None;
+    let mut found = Vec::new();
+    for (r, row) in rows.iter().enumerate() {
+        for (x, c) in row.iter().enumerate() {
+            let xy = XYCell::new(x as i16, nrows - 1 - r as i16);
+            if *c == GLYPH_ORIGIN {
+                origin = Some(xy);
+            } else if let Some(o) = orientation_from_glyph(*c) {
+                found.push(Coords::from(xy, o));
+            } else if *c != GLYPH_EMPTY {
+                return Err(format!("invalid glyph {c:?} at row {r}"));
+            }
+        }
+    }
+    if let Some(i) = plans.keys().find(|i| **i >= found.len()) {
+        return Err(format!(
+            "plan for agent {i} but there are {} agents",
+            found.len()
+        ));
+    }
+    let offset = match (origin, bottom_left) {
+        (Some(_), Some(_)) => return Err("both an origin glyph and an origin line".to_string()),
+        (Some(origin), None) => XYCell::new(0, 0) - origin,
+        (None, Some(bottom_left)) => bottom_left,
+        (None, None) => XYCell::new(0, 0),
+    };
+    let mut agents = Vec::new();
+    for (i, coord) in found.into_iter().enumerate() {
+        let agent = match plans.remove(&i).or(default_plan.clone()) {
+            Some(agent) => agent,
+            None => return Err(format!("no plan for agent {i}")),
+        };
+        let coord = Coords::from(coord.xy + offset, coord.orientation);
+        agents.push(ArbAgent { coord, ..agent });
+    }
+    Ok(ArbSetup { agents })
+}
+
+fn parse_xy(text: &str) -> Option<XYCell> {
+    let (x, y) = text.split_whitespace().collect_tuple()?;
+    Some(XYCell::new(x.parse().ok()?, y.parse().ok()?))
+}
+
+/// The actions and the options of a plan line; the coordinates are left at the origin.
+fn parse_plan(text: &str) -> Result<ArbAgent, String> {
+    let mut agent = ArbAgent::new(Coords::from(XYCell::new(0, 0), Orientations::NORTH), vec![]);
+    for (k, word) in text.split_whitespace().enumerate() {
+        match word {
+            "stay" => agent.end = PlanEnd::Stay,
+            "disappear" => agent.end = PlanEnd::Disappear,
+            _ if word.starts_with("priority=") => {
+                agent.priority = word["priority=".len()..]
+                    .parse()
+                    .map_err(|_| format!("invalid priority {word:?}"))?;
+            }
+            _ if k == 0 => {
+                agent.plan = word
+                    .chars()
+                    .map(|c| action_from_char(c).ok_or(format!("invalid action {c:?}")))
+                    .collect::<Result<Vec<_>, _>>()?;
+            }
+            _ => return Err(format!("invalid option {word:?}")),
+        }
+    }
+    Ok(agent)
+}
+
+/// The plan line of an agent, without its number
+fn format_plan(agent: &ArbAgent) -> String {
+    let mut words = vec![agent.plan.iter().map(|a| format!("{a:?}")).join("")];
+    match agent.end {
+        PlanEnd::Unknown => {}
+        PlanEnd::Stay => words.push("stay".to_string()),
+        PlanEnd::Disappear => words.push("disappear".to_string()),
+    }
+    if agent.priority != 0 {
+        words.push(format!("priority={}", agent.priority));
+    }
+    words.join(" ")
+}
+
+/// Writes the scenario in the format read by `parse_scenario`.
+/// The agents are renumbered in reading order.
+/// Vehicles other than cars and agents sharing a cell cannot be written.
+pub fn format_scenario(s: &ArbSetup) -> Result<String, String> {
+    if let Some(a) = s
+        .agents
+        .iter()
+        .find(|a| !a.vehicle.is_unit() || !a.trail.is_empty())
+    {
+        return Err(format!(
+            "cannot write the vehicle of the agent at {:?}",
+            a.coord
+        ));
+    }
+    let xys = s.agents.iter().map(|a| a.coord.xy).collect_vec();
+    let min_x = xys.iter().map(|xy| xy.x).min().unwrap_or(0).min(0);
+    let max_x = xys.iter().map(|xy| xy.x).max().unwrap_or(0).max(0);
+    let min_y = xys.iter().map(|xy| xy.y).min().unwrap_or(0).min(0);
+    let max_y = xys.iter().map(|xy| xy.y).max().unwrap_or(0).max(0);
+    let mut lines = Vec::new();
+    let mut order = Vec::new();
+    for y in (min_y..=max_y).rev() {
+        let mut row = Vec::new();
+        for x in min_x..=max_x {
+            let xy = XYCell::new(x, y);
+            let here = (0..s.agents.len())
+                .filter(|i| s.agents[*i].coord.xy == xy)
+                .collect_vec();
+            let glyph = match here.as_slice() {
+                [] if xy == XYCell::new(0, 0) => GLYPH_ORIGIN,
+                [] => GLYPH_EMPTY,
+                [i] => {
+                    order.push(*i);
+                    glyph_from_orientation(s.agents[*i].coord.orientation)
+                }
+                _ => return Err(format!("several agents at {xy:?}")),
+            };
+            row.push(glyph.to_string());
+        }
+        lines.push(row.join(" "));
+    }
+    if xys.contains(&XYCell::new(0, 0)) {
+        lines.push(format!("{ORIGIN_LINE}: {min_x} {min_y}"));
+    }
+    let plans = order
+        .iter()
+        .map(|i| format_plan(&s.agents[*i]))
+        .collect_vec();
+    if !plans.is_empty() && plans.iter().all_equal() {
+        lines.push(format!("*: {}", plans[0]));
+    } else {
+        for (k, plan) in plans.iter().enumerate() {
+            lines.push(format!("{k}: {plan}"));
+        }
+    }
+    Ok(lines.join("\n") + "\n")
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use itertools::Itertools;
+
+    use crate::*;
+
+    fn nsolutions(text: &str) -> usize {
+        let setup = parse_scenario(text).unwrap();
+        find_feasible_plans(&setup, 0).solutions.len()
+    }
+
+    #[test]
+    fn test_parse_four_way() {
+        let text = "
+            . v . .
+            . . + <
+            > . . .
+            . . ^ .
+            *: FFF
+        ";
+        let setup = parse_scenario(text).unwrap();
+        assert_eq!(setup.agents.len(), 4);
+        // numbered in reading order: from the north, east, west, south
+        assert_eq!(
+            setup.agents[0].coord,
+            Coords::from(XYCell::new(-1, 1), Orientations::SOUTH)
+        );
+        assert_eq!(
+            setup.agents[1].coord,
+            Coords::from(XYCell::new(1, 0), Orientations::WEST)
+        );
+        // same as test_arb1: 4 + 2 criss cross
+        assert_eq!(nsolutions(text), 6);
+    }
+
+    #[test]
+    fn test_format_round_trip() {
+        let text = "
+            . v . .
+            . . + <
+            > . . .
+            *: FFF
+            2: FLFF
+        ";
+        let setup = parse_scenario(text).unwrap();
+        let formatted = format_scenario(&setup).unwrap();
+        assert_eq!(parse_scenario(&formatted).unwrap(), setup);
+        assert!(formatted.contains("2: FLFF"));
+    }
+
+    #[test]
+    fn test_format_round_trip_library() {
+        for scenario in canonical_scenarios() {
+            let mut setup = scenario.setup.clone();
+            for (i, agent) in setup.agents.iter_mut().enumerate() {
+                agent.end = [PlanEnd::Unknown, PlanEnd::Stay, PlanEnd::Disappear][i % 3];
+                agent.priority = (i % 2) as PriorityClass;
+            }
+            for setup in [&scenario.setup, &setup] {
+                let formatted = format_scenario(setup).unwrap();
+                let parsed = parse_scenario(&formatted).unwrap();
+                // renumbered in reading order
+                assert_eq!(
+                    parsed.agents.iter().collect::<HashSet<_>>(),
+                    setup.agents.iter().collect::<HashSet<_>>(),
+                    "{}:\n{formatted}",
+                    scenario.name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_agent_on_origin() {
+        // the origin is not the bottom-left cell and an agent sits on it
+        let text = "
+            . v
+            > .
+            +: -1 0
+            *: FF disappear
+            1: FF priority=2
+        ";
+        let setup = parse_scenario(text).unwrap();
+        assert_eq!(
+            setup.agents[0].coord,
+            Coords::from(XYCell::new(0, 1), Orientations::SOUTH)
+        );
+        assert_eq!(setup.agents[1].coord.xy, XYCell::new(-1, 0));
+        assert_eq!(setup.agents[1].priority, 2);
+        assert_eq!(setup.agents[1].end, PlanEnd::Unknown);
+        assert_eq!(setup.agents[0].end, PlanEnd::Disappear);
+        let shifted = ArbSetup {
+            agents: vec![ArbAgent::new(
+                Coords::from(XYCell::new(0, 0), Orientations::EAST),
+                vec![Actions::Forward],
+            )],
+        };
+        let mut moved = shifted.clone();
+        moved.agents.push(ArbAgent::new(
+            Coords::from(XYCell::new(-2, -1), Orientations::NORTH),
+            vec![],
+        ));
+        for setup in [shifted, moved] {
+            let formatted = format_scenario(&setup).unwrap();
+            assert!(formatted.contains("+: "), "{formatted}");
+            assert_eq!(
+                parse_scenario(&formatted)
+                    .unwrap()
+                    .agents
+                    .into_iter()
+                    .collect::<HashSet<_>>(),
+                setup.agents.into_iter().collect::<HashSet<_>>()
+            );
+        }
+        let bus = ArbAgent::new(Coords::from(XYCell::new(0, 0), Orientations::EAST), vec![])
+            .with_vehicle(Vehicle::BUS, vec![]);
+        assert!(format_scenario(&ArbSetup { agents: vec![bus] }).is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse_scenario("> x\n*: F").is_err());
+        assert!(parse_scenario("> .\n*: FX").is_err());
+        assert!(parse_scenario("> .").is_err());
+        assert!(parse_scenario("> .\n*: F\n1: F").is_err());
+        assert!(parse_scenario("> .\n*: F park").is_err());
+        assert!(parse_scenario("> +\n+: 0 0\n*: F").is_err());
+    }
+
+    #[test]
+    fn test_queue() {
+        // a queue has a single solution whatever the order
+        assert_eq!(nsolutions("+ < < <\n*: FFF"), 1);
+    }
+
+    #[test]
+    fn test_head_on_in_separate_lanes() {
+        // two lanes in opposite directions do not interact
+        let text = "
+            < . + .
+            . . . >
+            *: FFF
+        ";
+        let setup = parse_scenario(text).unwrap();
+        let result = find_feasible_plans(&setup, 0);
+        assert_eq!(result.solutions.keys().collect_vec(), vec![&vec![0; 2]]);
+    }
+
+    #[test]
+    fn test_crossing_two() {
+        // two agents crossing: one of them yields
+        let text = "
+            . . + <
+            . . ^ .
+            *: FFF
+        ";
+        assert_eq!(nsolutions(text), 2);
+    }
+
+    #[test]
+    fn test_left_turn_against_straight() {
+        // the agent from the south turns left across the one coming from the east
+        let text = "
+            . + <
+            . ^ .
+            0: FFF
+            1: FLFF
+        ";
+        let setup = parse_scenario(text).unwrap();
+        let result = find_feasible_plans(&setup, 0);
+        assert_eq!(result.solutions.len(), 2);
+        for c in result.solutions.keys() {
+            assert!(c.iter().filter(|x| **x > 0).count() == 1);
+        }
+    }
+}
@@ -0,0 +1,239 @@
+use itertools::Itertools;
+
+use crate::{
+    glyph_from_orientation, orientation_from_glyph, Cell, Coords, Grid, Orientations, XYCell,
+    NUM_ORIENTATIONS,
+};
+
+pub const GLYPH_TERRAIN: char = '.';
+pub const GLYPH_OBSTACLE: char = '#';
+
+fn orientation_from_index(i: usize) -> Orientations {
+    [
+        Orientations::NORTH,
+        Orientations::SOUTH,
+        Orientations::WEST,
+        Orientations::EAST,
+    ][i]
+}
+
+/// The orientations whose bits are set in the mask, as in `Cell::orientation_mask`
+pub fn orientations_from_mask(mask: u8) -> Vec<Orientations> {
+    (0..NUM_ORIENTATIONS)
+        .filter(|i| mask & (1 << i) != 0)
+        .map(orientation_from_index)
+        .collect_vec()
+}
+
+/// The glyph of a road allowing the orientations of the mask
+pub fn glyph_from_mask(mask: u8) -> char {
+    match mask {
+        0 => GLYPH_TERRAIN,
+        0b0011 => '|',
+        0b1100 => '-',
+        0b1111 => '+',
+        m if m.count_ones() == 1 => {
+            glyph_from_orientation(orientation_from_index(m.trailing_zeros() as usize))
+        }
+        m => std::char::from_digit(m as u32, 16).unwrap(),
+    }
+}
+
+pub fn mask_from_glyph(c: char) -> Option<u8> {
+    match c {
+        '|' => Some(0b0011),
+        '-' => Some(0b1100),
+        '+' => Some(0b1111),
+        c => match orientation_from_glyph(c) {
+            Some(o) => Some(1 << o as usize),
+            None => c
+                .to_digit(16)
+                .filter(|m| *m != 0 && !c.is_ascii_uppercase())
+                .map(|m| m as u8),
+        },
+    }
+}
+
+pub fn parking_from_glyph(c: char) -> Option<Orientations> {
+    match c {
+        'N' => Some(Orientations::NORTH),
+        'S' => Some(Orientations::SOUTH),
+        'W' => Some(Orientations::WEST),
+        'E' => Some(Orientations::EAST),
+        _ => None,
+    }
+}
+
+pub fn charging_from_glyph(c: char) -> Option<Orientations> {
+    match c {
+        'U' => Some(Orientations::NORTH),
+        'D' => Some(Orientations::SOUTH),
+        'L' => Some(Orientations::WEST),
+        'R' => Some(Orientations::EAST),
+        _ => None,
+    }
+}
+
+fn glyph_from_parking(o: Orientations) -> char {
+    match o {
+        Orientations::NORTH => 'N',
+        Orientations::SOUTH => 'S',
+        Orientations::WEST => 'W',
+        Orientations::EAST => 'E',
+    }
+}
+
+fn glyph_from_charging(o: Orientations) -> char {
+    match o {
+        Orientations::NORTH => 'U',
+        Orientations::SOUTH => 'D',
+        Orientations::WEST => 'L',
+        Orientations::EAST => 'R',
+    }
+}
+
+/// Parses a map with one glyph per cell; the top row has the largest y.
+///
+/// ```text
+/// ..N.....
+/// <<-<<<<<
+/// >>->>>>>
+/// ..|..#..
+/// ..|.R...
+/// ```
+///
+/// - `.` terrain and `#` obstacle;
+/// - `^ v < >` one-way roads, `|` and `-` two-way roads, `+` all four orientations;
+/// - a lowercase hex digit for any other set of orientations (N = 1, S = 2, W = 4, E = 8);
+/// - `N S W E` parking spots facing that way, `U D L R` charging spots facing up, down,
+///   left and right.
+///
+/// Spaces are ignored; empty lines and lines starting with `//` are skipped.
+/// The cells get the default actions of their orientations.
+pub fn parse_grid(text: &str) -> Result<Grid, String> {
+    let rows = text
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with("//"))
+        .map(|line| line.chars().filter(|c| !c.is_whitespace()).collect_vec())
+        .collect_vec();
+    let Some(width) = rows.first().map(|row| row.len()) else {
+        return Err("empty map".to_string());
+    };
+    if let Some(r) = rows.iter().position(|row| row.len() != width) {
+        return Err(format!(
+            "row {r} has {} cells instead of {width}",
+            rows[r].len()
+        ));
+    }
+    let nrows = rows.len() as i16;
+    let mut grid = Grid::new(XYCell::new(width as i16, nrows));
+    for (r, row) in rows.iter().enumerate() {
+        for (x, c) in row.iter().enumerate() {
+            let xy = XYCell::new(x as i16, nrows - 1 - r as i16);
+            if *c == GLYPH_TERRAIN {
+                continue;
+            } else if *c == GLYPH_OBSTACLE {
+                grid.replace_cell(&xy, Cell::obstacle());
+            } else if let Some(o) = parking_from_glyph(*c) {
+                grid.replace_cell(&xy, Cell::parking(o));
+            } else if let Some(o) = charging_from_glyph(*c) {
+                grid.replace_cell(&xy, Cell::charging(o));
+            } else if let Some(mask) = mask_from_glyph(*c) {
+                for o in orientations_from_mask(mask) {
+                    grid.draw_road(&Coords::from(xy, o));
+                }
+            } else {
+                return Err(format!("invalid glyph {c:?} at row {r}"));
+            }
+        }
+    }
+    Ok(grid)
+}
+
+/// The glyph of the cell in the format read by `parse_grid`.
+/// Parking and charging spots keep only their first orientation.
+pub fn glyph_from_cell(cell: &Cell) -> char {
+    let mask = cell.orientation_mask();
+    let first = orientations_from_mask(mask).first().copied();
+    match first {
+        _ if cell.is_obstacle => GLYPH_OBSTACLE,
+        Some(o) if cell.is_parking => glyph_from_parking(o),
+        Some(o) if cell.is_charging => glyph_from_charging(o),
+        _ => glyph_from_mask(mask),
+    }
+}
+
+/// Writes the grid in the format read by `parse_grid`.
+pub fn format_grid(grid: &Grid) -> String {
+    let mut lines = Vec::new();
+    for y in (0..grid.size.y).rev() {
+        let line: String = (0..grid.size.x)
+            .map(|x| glyph_from_cell(grid.get_cell(&XYCell::new(x, y))))
+            .collect();
+        lines.push(line);
+    }
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    const MAP: &str = "
+        // a two-way street with a parking spot, a charging spot and a building
+        ..N..#..
+        <<-<<<<<
+        >>->>>>>
+        ..|..#..
+        ..|.R.5.
+    ";
+
+    #[test]
+    fn test_parse_map() {
+        let grid = parse_grid(MAP).unwrap();
+        assert_eq!(grid.size, XYCell::new(8, 5));
+        let parking = grid.get_cell(&XYCell::new(2, 4));
+        assert!(parking.is_parking);
+        assert!(parking.is_allowed(Orientations::NORTH));
+        assert!(grid.get_cell(&XYCell::new(4, 0)).is_charging);
+        assert!(grid.get_cell(&XYCell::new(5, 4)).is_obstacle);
+        assert!(!grid.get_cell(&XYCell::new(5, 4)).traversable());
+        let crossing = grid.get_cell(&XYCell::new(2, 3));
+        assert_eq!(crossing.orientation_mask(), 0b1100);
+        // 5 = north and west
+        let odd = grid.get_cell(&XYCell::new(6, 0));
+        assert!(odd.is_allowed(Orientations::NORTH) && odd.is_allowed(Orientations::WEST));
+        assert_eq!(grid.empty_parking_cells.queue.len(), 1);
+    }
+
+    #[test]
+    fn test_map_round_trip() {
+        let grid = parse_grid(MAP).unwrap();
+        let text = format_grid(&grid);
+        assert_eq!(text, "..N..#..\n<<-<<<<<\n>>->>>>>\n..|..#..\n..|.R.5.\n");
+        assert_eq!(format_grid(&parse_grid(&text).unwrap()), text);
+    }
+
+    #[test]
+    fn test_generated_map_round_trip() {
+        let mut rng = rng_from_seed(0);
+        let block = Block::with_parking(XYCell::new(16, 16), 2, &mut rng);
+        let text = format_grid(&block.grid);
+        let grid = parse_grid(&text).unwrap();
+        assert_eq!(format_grid(&grid), text);
+        for (xy, cell) in block.grid.iterate_cells() {
+            let parsed = grid.get_cell(&xy);
+            assert_eq!(parsed.orientation_mask(), cell.orientation_mask());
+            assert_eq!(parsed.is_parking, cell.is_parking);
+        }
+    }
+
+    #[test]
+    fn test_map_errors() {
+        assert!(parse_grid("").is_err());
+        assert!(parse_grid("..\n...").is_err());
+        assert!(parse_grid("..\n.0").is_err());
+        assert!(parse_grid("..\n.x").is_err());
+    }
+}
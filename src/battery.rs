@@ -0,0 +1,163 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Actions;
+
+/// Energy used and recovered by the robots, in arbitrary units
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatteryModel {
+    pub capacity: u32,
+    /// Energy used by a move forward or backward
+    pub move_cost: u32,
+    /// Energy used by a turn in place
+    pub turn_cost: u32,
+    /// Energy gained per step by a robot waiting on a charging cell
+    pub charge_rate: u32,
+}
+
+impl BatteryModel {
+    /// Moves and turns cost 1 and a full charge takes 20 steps
+    pub fn new(capacity: u32) -> Self {
+        Self {
+            capacity,
+            move_cost: 1,
+            turn_cost: 1,
+            charge_rate: (capacity / 20).max(1),
+        }
+    }
+
+    pub fn cost(&self, action: Actions) -> u32 {
+        match action {
+            Actions::Wait => 0,
+            Actions::Forward | Actions::Backward => self.move_cost,
+            Actions::TurnLeft | Actions::TurnRight => self.turn_cost,
+        }
+    }
+
+    /// The charge after the action; robots waiting on a charging cell recharge.
+    pub fn after(&self, charge: u32, action: Actions, on_charger: bool) -> u32 {
+        if on_charger && action == Actions::Wait {
+            (charge + self.charge_rate).min(self.capacity)
+        } else {
+            charge.saturating_sub(self.cost(action))
+        }
+    }
+
+    /// Replaces with waits the plan from its first action that the charge does not cover.
+    /// Returns whether there was one.
+    pub fn restrict(&self, charge: u32, plan: &mut [Actions]) -> bool {
+        let mut left = charge;
+        let short = plan
+            .iter()
+            .position(|action| match left.checked_sub(self.cost(*action)) {
+                Some(l) => {
+                    left = l;
+                    false
+                }
+                None => true,
+            });
+        match short {
+            Some(dt) => {
+                plan[dt..].fill(Actions::Wait);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    /// A road going east with a charging spot off its end
+    const MAP: &str = "
+        ...U
+        >>>9
+    ";
+
+    #[test]
+    fn test_battery_model() {
+        let model = BatteryModel::new(100);
+        assert_eq!(model.after(10, Actions::Forward, false), 9);
+        assert_eq!(model.after(0, Actions::TurnLeft, false), 0);
+        assert_eq!(model.after(10, Actions::Wait, false), 10);
+        assert_eq!(model.after(10, Actions::Wait, true), 15);
+        assert_eq!(model.after(98, Actions::Wait, true), 100);
+        let mut plan = vec![
+            Actions::Forward,
+            Actions::Wait,
+            Actions::TurnLeft,
+            Actions::Forward,
+        ];
+        assert!(model.restrict(1, &mut plan));
+        assert_eq!(
+            plan,
+            vec![
+                Actions::Forward,
+                Actions::Wait,
+                Actions::Wait,
+                Actions::Wait
+            ]
+        );
+    }
+
+    #[test]
+    fn test_drain_strand_and_charge() {
+        let grid = parse_grid(MAP).unwrap();
+        let mut world = World::new(grid);
+        world.place_robot(Coords::from(XY::new(0, 0), Orientations::EAST));
+        world.set_battery_model(BatteryModel {
+            capacity: 4,
+            move_cost: 1,
+            turn_cost: 1,
+            charge_rate: 2,
+        });
+        world.robots[0].battery = 2;
+        assert_eq!(
+            world.charging_cells(),
+            vec![Coords::from(XY::new(3, 1), Orientations::NORTH)]
+        );
+        // east to the end of the road, north into the charger, then wait there
+        let route = [
+            Actions::Forward,
+            Actions::Forward,
+            Actions::Forward,
+            Actions::TurnLeft,
+            Actions::Forward,
+        ];
+        let mut step = 0;
+        let mut f = |_: &mut RNG, _: usize, _: &Robot, horizon: usize| {
+            let mut plan = vec![Actions::Wait; horizon];
+            plan[0] = route.get(step).copied().unwrap_or(Actions::Wait);
+            step += 1;
+            plan
+        };
+        let mut rng = rng_from_seed(0);
+        for _ in 0..3 {
+            world.step_robots(&mut f, &mut rng);
+        }
+        // the battery ran out after two moves
+        assert_eq!(world.robots[0].coords.xy, XY::new(2, 0));
+        assert_eq!(world.robots[0].battery, 0);
+        assert_eq!(world.stats.strandings, 1);
+        assert_eq!(world.stats.stranded_steps, 1);
+
+        // the rest of the route, once recharged by hand
+        world.robots[0].battery = 3;
+        let mut step = 2;
+        let mut f = |_: &mut RNG, _: usize, _: &Robot, horizon: usize| {
+            let mut plan = vec![Actions::Wait; horizon];
+            plan[0] = route.get(step).copied().unwrap_or(Actions::Wait);
+            step += 1;
+            plan
+        };
+        for _ in 0..4 {
+            world.step_robots(&mut f, &mut rng);
+        }
+        assert_eq!(world.robots[0].coords.xy, XY::new(3, 1));
+        // it arrived empty, then charged for one step
+        assert_eq!(world.robots[0].battery, 2);
+        assert_eq!(world.stats.charging_steps, 1);
+        assert_eq!(world.stats.strandings, 1);
+    }
+}
@@ -134,12 +134,12 @@ impl BlockMap {
         self.blocks[x][y] = block;
     }
 
-    pub fn stitch(&self) -> Grid {
+    pub fn stitch(&self, rng: &mut RNG) -> Grid {
         let bigsize = XY::new(
             self.size.x * self.block_size.x,
             self.size.y * self.block_size.y,
         );
-        let mut grid = Grid::new(bigsize);
+        let mut grid = Grid::with_rng(bigsize, rng);
         for x in 0..self.size.x {
             for y in 0..self.size.y {
                 let block = &self.blocks[x as usize][y as usize];
@@ -193,6 +193,8 @@ impl BlockMap {
 
 #[cfg(test)]
 mod tests {
+    use crate::rng_from_seed;
+
     use super::*;
 
     #[test]
@@ -200,12 +202,12 @@ mod tests {
         let map_size = Size::new(16, 24);
         let block_size = Size::new(32, 10);
         let mut bl = BlockMap::new(map_size, block_size);
-        let mut rng = rand::thread_rng();
+        let mut rng = rng_from_seed(0);
         for p in map_size.iterate_xy() {
             bl.set_block(p, Block::basic_with_roads(block_size, &mut rng));
         }
 
-        let g = bl.stitch();
+        let g = bl.stitch(&mut rng);
 
         // draw the map
     }
@@ -0,0 +1,125 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::{World, RNG};
+
+/// The state of a running simulation; restoring it continues the run exactly.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint<A> {
+    /// Number of steps already simulated
+    pub step: usize,
+    pub world: World,
+    pub rng: RNG,
+    /// State of the agents driving the robots, by robot name
+    pub agents: Vec<A>,
+}
+
+impl<A> Checkpoint<A>
+where
+    A: Serialize + DeserializeOwned,
+{
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        bincode::serialize(self).map_err(|e| format!("cannot serialize checkpoint: {e}"))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        bincode::deserialize(bytes).map_err(|e| format!("cannot deserialize checkpoint: {e}"))
+    }
+
+    pub fn save(&self, filename: &str) -> Result<(), String> {
+        std::fs::write(filename, self.to_bytes()?)
+            .map_err(|e| format!("cannot save {filename}: {e}"))
+    }
+
+    pub fn load(filename: &str) -> Result<Self, String> {
+        let bytes = std::fs::read(filename).map_err(|e| format!("cannot read {filename}: {e}"))?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+/// Serializes `image::Rgb<u8>`, which does not implement serde, as its 3 channels.
+pub(crate) mod serde_rgb {
+    use image::Rgb;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(color: &Rgb<u8>, s: S) -> Result<S::Ok, S::Error> {
+        color.0.serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Rgb<u8>, D::Error> {
+        Ok(Rgb(<[u8; 3]>::deserialize(d)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use itertools::Itertools;
+    use rand::seq::SliceRandom;
+
+    use crate::*;
+
+    fn small_world(rng: &mut RNG) -> World {
+        let map_size = XY::new(4, 3);
+        let block_size = XY::new(16, 16);
+        let mut bl = BlockMap::new(map_size, block_size);
+        for p in map_size.iterate_xy_interior() {
+            bl.set_block(p, Block::with_parking(block_size, 2, rng));
+        }
+        let mut world = World::new(bl.stitch(rng));
+        let parkings = world
+            .grid
+            .empty_parking_cells
+            .queue
+            .clone()
+            .into_sorted_vec();
+        for xy in parkings.iter().take(8) {
+            let orientation = world.grid.get_cell(xy).random_direction(rng);
+            world.place_robot(Coords::from(*xy, orientation));
+        }
+        world
+    }
+
+    /// Random walks; each agent counts the steps it planned.
+    fn run(c: &mut Checkpoint<usize>, map: &World, nsteps: usize) {
+        let agents = &mut c.agents;
+        let mut f = |rng: &mut RNG, name: usize, robot: &Robot, horizon: usize| {
+            agents[name] += 1;
+            let mut coords = robot.coords;
+            (0..horizon)
+                .map(|_| {
+                    let actions = map.allowed_robot_actions_if_empty(&coords);
+                    let action = *actions.choose(rng).unwrap();
+                    coords = next_coords(&coords, action);
+                    action
+                })
+                .collect_vec()
+        };
+        for _ in 0..nsteps {
+            c.world.step_robots(&mut f, &mut c.rng);
+            c.step += 1;
+        }
+    }
+
+    #[test]
+    fn test_restore_continues_the_run() {
+        let mut rng = rng_from_seed(3);
+        let world = small_world(&mut rng);
+        let map = world.clone();
+        let agents = vec![0; world.robots.len()];
+        let mut c = Checkpoint {
+            step: 0,
+            world,
+            rng,
+            agents,
+        };
+        run(&mut c, &map, 5);
+        let mut restored = Checkpoint::<usize>::from_bytes(&c.to_bytes().unwrap()).unwrap();
+        assert_eq!(restored.step, 5);
+        run(&mut c, &map, 10);
+        run(&mut restored, &map, 10);
+        assert_eq!(restored.world.robots, c.world.robots);
+        assert_eq!(restored.world.stats, c.world.stats);
+        assert_eq!(restored.agents, c.agents);
+        assert_eq!(restored.rng, c.rng);
+    }
+}
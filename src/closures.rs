@@ -0,0 +1,156 @@
+use std::collections::HashSet;
+
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::{next_coords, Actions, Coords, Grid, Orientations, XYCell, NUM_ORIENTATIONS};
+
+/// A part of the road closed at runtime, e.g. for roadworks or after an accident
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Closure {
+    pub xy: XYCell,
+    /// Only this direction of travel; the whole cell if none
+    pub orientation: Option<Orientations>,
+}
+
+impl Closure {
+    pub fn cell(xy: XYCell) -> Self {
+        Self {
+            xy,
+            orientation: None,
+        }
+    }
+
+    pub fn lane(coords: Coords) -> Self {
+        Self {
+            xy: coords.xy,
+            orientation: Some(coords.orientation),
+        }
+    }
+
+    /// The coordinates it covers, allowed by the map or not
+    pub fn coords(&self) -> Vec<Coords> {
+        match self.orientation {
+            Some(o) => vec![Coords::from(self.xy, o)],
+            None => (0..NUM_ORIENTATIONS)
+                .map(|i| Coords::from(self.xy, Orientations::from_index(i)))
+                .collect_vec(),
+        }
+    }
+}
+
+/// The coordinates closed at runtime. Only what the map allowed is recorded,
+/// so that reopening gives back the original map.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Closures {
+    closed: HashSet<Coords>,
+}
+
+impl Closures {
+    pub fn is_empty(&self) -> bool {
+        self.closed.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.closed.len()
+    }
+
+    pub fn is_closed(&self, coords: &Coords) -> bool {
+        self.closed.contains(coords)
+    }
+
+    /// Forbids the coordinates of the closure in the grid; returns the ones newly closed.
+    pub fn close(&mut self, grid: &mut Grid, closure: Closure) -> Vec<Coords> {
+        let mut closed = Vec::new();
+        for c in closure.coords() {
+            let cell = grid.get_cell_mut(&c.xy);
+            if cell.is_allowed(c.orientation) {
+                cell.set_unallowed(c.orientation);
+                self.closed.insert(c);
+                closed.push(c);
+            }
+        }
+        closed
+    }
+
+    /// Allows again what the closure closed; returns the coordinates reopened.
+    pub fn reopen(&mut self, grid: &mut Grid, closure: Closure) -> Vec<Coords> {
+        let mut reopened = Vec::new();
+        for c in closure.coords() {
+            if self.closed.remove(&c) {
+                grid.get_cell_mut(&c.xy).set_allowed(c.orientation);
+                reopened.push(c);
+            }
+        }
+        reopened
+    }
+
+    /// Whether the path goes through closed coordinates
+    pub fn crosses<'a>(&self, path: impl IntoIterator<Item = &'a Coords>) -> bool {
+        path.into_iter().any(|c| self.is_closed(c))
+    }
+
+    /// Replaces with waits the plan from its first action entering closed coordinates.
+    /// Robots caught inside a closed cell may still leave it.
+    /// Returns whether there was one.
+    pub fn restrict(&self, start: Coords, plan: &mut [Actions]) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        let mut coords = start;
+        let blocked = plan.iter().position(|action| {
+            coords = next_coords(&coords, *action);
+            coords.xy != start.xy && self.is_closed(&coords)
+        });
+        match blocked {
+            Some(dt) => {
+                plan[dt..].fill(Actions::Wait);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    const F: Actions = Actions::Forward;
+
+    #[test]
+    fn test_close_and_reopen() {
+        let grid = parse_grid("------------").unwrap();
+        let mut world = World::new(grid);
+        world.place_robot(Coords::from(XY::new(0, 0), Orientations::EAST));
+        let eastbound = Coords::from(XY::new(2, 0), Orientations::EAST);
+        assert_eq!(world.close(Closure::lane(eastbound)), vec![eastbound]);
+        let before = Coords::from(XY::new(1, 0), Orientations::EAST);
+        assert!(!world.allowed_robot_actions_if_empty(&before).contains(&F));
+        // the other direction is still open
+        let westbound = Coords::from(XY::new(3, 0), Orientations::WEST);
+        assert!(world
+            .allowed_robot_actions_if_empty(&westbound)
+            .contains(&F));
+
+        let mut f = |_: &mut RNG, _: usize, _: &Robot, horizon: usize| vec![F; horizon];
+        let mut rng = rng_from_seed(0);
+        for _ in 0..3 {
+            world.step_robots(&mut f, &mut rng);
+        }
+        assert_eq!(world.robots[0].xy(), XY::new(1, 0));
+        assert_eq!(world.stats.closure_stops, 3);
+
+        // closing the whole cell only adds the other direction
+        let closure = Closure::cell(XY::new(2, 0));
+        assert_eq!(world.close(closure).len(), 1);
+        assert_eq!(world.reopen(closure).len(), 2);
+        assert!(world.closures.is_empty());
+        assert_eq!(
+            world.grid.get_cell(&XY::new(2, 0)).orientation_mask(),
+            0b1100
+        );
+        world.step_robots(&mut f, &mut rng);
+        assert_eq!(world.robots[0].xy(), XY::new(2, 0));
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::{min_social_cost, social_cost, ArbResult, Cost, Costs, RobotName};
+
+/// What to do with the order chosen at the previous step
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum CommitmentPolicy {
+    /// Re-solve every game from scratch
+    #[default]
+    Renegotiate,
+    /// Keep the previous order whenever it is still feasible
+    Honor,
+    /// Keep the previous order unless its total cost exceeds the best one by more than `tolerance`
+    HonorWithin { tolerance: Cost },
+}
+
+/// For each robot, the previous order it was part of and its rank in it.
+pub fn ranks_from_orders(orders: &[Vec<RobotName>]) -> HashMap<RobotName, (usize, usize)> {
+    let mut res = HashMap::new();
+    for (group, order) in orders.iter().enumerate() {
+        for (rank, name) in order.iter().enumerate() {
+            res.insert(*name, (group, rank));
+        }
+    }
+    res
+}
+
+/// The order committed to for the players of a game, as a permutation of the game indices.
+///
+/// Clusters merge and split from one step to the next, so the order is derived pair by pair:
+/// players ordered in the same previous game keep their relative order, players of different
+/// previous games are interleaved by rank, and players without history go last.
+/// There is none if no two players were ordered at the previous step.
+pub fn committed_order(
+    ranks: &HashMap<RobotName, (usize, usize)>,
+    index2name: &[RobotName],
+) -> Option<Vec<usize>> {
+    let groups = index2name
+        .iter()
+        .filter_map(|name| ranks.get(name).map(|(g, _)| *g))
+        .collect_vec();
+    if groups.iter().all_unique() {
+        return None;
+    }
+    let perm = (0..index2name.len())
+        .sorted_by_key(|i| match ranks.get(&index2name[*i]) {
+            Some((group, rank)) => (*rank, *group),
+            None => (usize::MAX, usize::MAX),
+        })
+        .collect_vec();
+    Some(perm)
+}
+
+/// Whether the committed solution is good enough to be kept, according to the policy.
+/// `committed` are its costs in the cost model the fronts of `result` are keyed by.
+pub fn keep_commitment(policy: CommitmentPolicy, committed: &Costs, result: &ArbResult) -> bool {
+    match policy {
+        CommitmentPolicy::Renegotiate => false,
+        CommitmentPolicy::Honor => true,
+        CommitmentPolicy::HonorWithin { tolerance } => match min_social_cost(result) {
+            None => true,
+            Some(best) => social_cost(committed) <= best + tolerance,
+        },
+    }
+}
+
+/// Counts the pairs of robots that were ordered in the previous step and are now in the
+/// opposite order. Returns the number of reversals and the number of pairs compared.
+pub fn count_order_reversals(
+    ranks: &HashMap<RobotName, (usize, usize)>,
+    order: &[RobotName],
+) -> (usize, usize) {
+    let mut reversals = 0;
+    let mut compared = 0;
+    for (i, a) in order.iter().enumerate() {
+        for b in &order[i + 1..] {
+            if let (Some((ga, ra)), Some((gb, rb))) = (ranks.get(a), ranks.get(b)) {
+                if ga == gb {
+                    compared += 1;
+                    if rb < ra {
+                        reversals += 1;
+                    }
+                }
+            }
+        }
+    }
+    (reversals, compared)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_order_reversals() {
+        let ranks = ranks_from_orders(&[vec![10, 11, 12], vec![20, 21]]);
+        assert_eq!(count_order_reversals(&ranks, &[10, 11, 12]), (0, 3));
+        assert_eq!(count_order_reversals(&ranks, &[12, 10, 21, 11, 20]), (3, 4));
+        assert_eq!(committed_order(&ranks, &[12, 10, 11]), Some(vec![1, 2, 0]));
+        assert_eq!(committed_order(&ranks, &[12, 20]), None);
+        assert_eq!(committed_order(&ranks, &[12, 30]), None);
+        // merged clusters, with a newcomer
+        assert_eq!(
+            committed_order(&ranks, &[20, 12, 10, 30, 21]),
+            Some(vec![2, 0, 4, 1, 3])
+        );
+        // part of a split cluster
+        assert_eq!(committed_order(&ranks, &[30, 12, 11]), Some(vec![2, 1, 0]));
+    }
+}
@@ -5,21 +5,41 @@ use std::fmt::Display;
 use std::hash::Hash;
 use std::ops::{Add, Sub};
 
+use itertools::Itertools;
 use num::integer::sqrt;
 use num::Num;
 use petgraph::graph::{NodeIndex, UnGraph};
 use petgraph::visit::Dfs;
 use rand::prelude::IteratorRandom;
-use rand::rngs::ThreadRng;
 use rand::seq::SliceRandom;
-
-use crate::{find_feasible_plans, ArbAgent, ArbSetup, ExtractedGame, SetSampler};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::checkpoint::serde_rgb;
+use crate::{
+    assign, blocking_chain, body_cells, committed_order, compare_fronts, count_order_reversals,
+    default_max_delay, evaluate, explain, find_exact_plans, find_feasible_plans_anytime_with,
+    find_hierarchical_plan, find_stackelberg_plans_with, format_blocking_chain, is_nash_stable,
+    keep_commitment, orientations_from_mask, pick_efficient, ranks_from_orders, vcg_payments,
+    ArbAgent, ArbCache, ArbResult, ArbSetup, ArbitrationConfig, ArbitrationStats, BatteryModel,
+    Closure, Closures, CommitmentPolicy, CostKind, ExtractedGame, Payment, PlanEnd, PriorityClass,
+    SetSampler, Signals, Vehicle,
+};
 
 // Rng trait must be in scope to use random methods
 
-pub type RNG = ThreadRng;
+/// The random generator used everywhere: seedable, so that runs can be reproduced
+pub type RNG = ChaCha8Rng;
+
+/// Seed used by the components that are not given a generator
+pub const DEFAULT_SEED: u64 = 0;
+
+pub fn rng_from_seed(seed: u64) -> RNG {
+    RNG::seed_from_u64(seed)
+}
 
-#[derive(Hash, Eq, PartialEq, Copy, Clone)]
+#[derive(Hash, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
 pub enum Orientations {
     NORTH = 0,
     SOUTH = 1,
@@ -106,7 +126,7 @@ impl Orientations {
     }
 }
 
-#[derive(Hash, Eq, PartialEq, Copy, Clone)]
+#[derive(Hash, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
 pub struct XY<T> {
     pub x: T,
     pub y: T,
@@ -185,7 +205,7 @@ pub type Plan = Vec<Actions>;
 pub type Cost = usize;
 pub type Costs = Vec<Cost>;
 
-#[derive(Hash, Eq, PartialEq, Clone, Copy)]
+#[derive(Hash, Eq, PartialEq, Clone, Copy, Serialize, Deserialize)]
 pub struct Coords {
     pub xy: XYCell,
     pub orientation: Orientations,
@@ -208,13 +228,25 @@ impl Coords {
     }
 }
 
-#[derive(Hash, Eq, PartialEq, Debug, Clone)]
+#[derive(Hash, Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub struct Robot {
     pub coords: Coords,
+    #[serde(with = "serde_rgb")]
     pub color: image::Rgb<u8>,
+    /// Robots of higher classes plan first if `config.stackelberg` is set
+    pub priority: PriorityClass,
+    /// Remaining energy, if the world has a battery model
+    pub battery: u32,
+    pub vehicle: Vehicle,
+    /// The cells behind `coords` covered by the body, nearest first
+    pub trail: Vec<XYCell>,
 }
 
 impl Robot {
+    /// The cells covered by the robot, head first
+    pub fn body(&self) -> Vec<XYCell> {
+        body_cells(&self.coords, &self.trail)
+    }
     pub fn orientation(&self) -> Orientations {
         self.coords.orientation
     }
@@ -223,7 +255,7 @@ impl Robot {
     }
 }
 
-#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct CellOr {
     pub robot_allowed: bool,
 
@@ -242,13 +274,16 @@ impl CellOr {
     }
 }
 
-#[derive(Eq, PartialEq, Debug, Clone)]
+#[derive(Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub struct Cell {
     pub present: Option<RobotName>,
     pub ors: [CellOr; NUM_ORIENTATIONS],
     pub is_parking: bool,
     pub is_charging: bool,
+    /// A building or other obstacle, never traversable
+    pub is_obstacle: bool,
 
+    #[serde(with = "serde_rgb")]
     pub color: image::Rgb<u8>,
 
     /// The block to which this cell belongs
@@ -270,6 +305,13 @@ impl Cell {
     pub fn is_allowed(&self, orientation: Orientations) -> bool {
         self.ors[orientation as usize].robot_allowed
     }
+    /// Bit `o as usize` is set for each allowed orientation `o`
+    pub fn orientation_mask(&self) -> u8 {
+        (0..NUM_ORIENTATIONS)
+            .filter(|i| self.ors[*i].robot_allowed)
+            .map(|i| 1 << i)
+            .sum()
+    }
     pub fn set_allowed(&mut self, orientation: Orientations) {
         self.ors[orientation as usize].robot_allowed = true;
     }
@@ -294,13 +336,41 @@ impl Cell {
             ors,
             is_parking: false,
             is_charging: false,
+            is_obstacle: false,
             block: None,
             color: image::Rgb::from(COLOR_TERRAIN),
         }
     }
+
+    /// A parking spot entered forward with this orientation and left backward
+    pub fn parking(orientation: Orientations) -> Self {
+        let mut cell = Cell::new();
+        cell.color = image::Rgb::from(COLOR_PARKING);
+        cell.is_parking = true;
+        cell.set_allowed(orientation);
+        cell.ors[orientation as usize].action_allowed[Actions::Backward as usize] = true;
+        cell
+    }
+
+    /// A charging spot, used like a parking spot
+    pub fn charging(orientation: Orientations) -> Self {
+        let mut cell = Cell::new();
+        cell.color = image::Rgb::from(COLOR_CHARGING);
+        cell.is_charging = true;
+        cell.set_allowed(orientation);
+        cell.ors[orientation as usize].action_allowed[Actions::Backward as usize] = true;
+        cell
+    }
+
+    pub fn obstacle() -> Self {
+        let mut cell = Cell::new();
+        cell.color = image::Rgb::from(COLOR_OBSTACLE);
+        cell.is_obstacle = true;
+        cell
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Grid {
     pub size: XY<i16>,
     pub cells: Vec<Vec<Cell>>,
@@ -309,41 +379,50 @@ pub struct Grid {
     empty_traversable_cells: SetSampler<XYCell>,
 }
 
+/// The elements are sorted first, so that the choice only depends on the generator.
 pub fn sample_from_hashset<T>(s: &HashSet<T>, rng: &mut RNG) -> T
 where
-    T: Clone,
+    T: Clone + Ord,
 {
-    let x = s.iter().choose(rng).unwrap();
+    let x = s.iter().sorted().choose(rng).unwrap();
     x.clone()
 }
 
 pub fn sample_from_hashmap<K, V>(s: &HashMap<K, V>, rng: &mut RNG) -> K
 where
-    K: Clone,
+    K: Clone + Ord,
 {
     if s.is_empty() {
         panic!("Empty hashmap");
     }
-    let (key, _) = s.iter().choose(rng).unwrap();
+    let key = s.keys().sorted().choose(rng).unwrap();
     key.clone()
 }
 
-const COLOR_ROAD: [u8; 3] = [0, 0, 0];
-const COLOR_TERRAIN: [u8; 3] = [0, 40, 0];
-const COLOR_PARKING: [u8; 3] = [0, 120, 120];
+/// Colours of the cells when rendered, see `render_grid_legend`
+pub const COLOR_ROAD: [u8; 3] = [0, 0, 0];
+pub const COLOR_TERRAIN: [u8; 3] = [0, 40, 0];
+pub const COLOR_PARKING: [u8; 3] = [0, 120, 120];
+pub const COLOR_CHARGING: [u8; 3] = [200, 160, 0];
+pub const COLOR_OBSTACLE: [u8; 3] = [90, 90, 90];
 
 impl Grid {
     pub fn set_valid(&mut self, c: &Coords) {
         self.get_cell_mut(&c.xy).set_allowed(c.orientation);
     }
     pub fn new(size: XY<i16>) -> Self {
+        Self::with_rng(size, &mut rng_from_seed(DEFAULT_SEED))
+    }
+
+    /// A blank grid whose samplers are seeded from `rng`.
+    pub fn with_rng(size: XY<i16>, rng: &mut RNG) -> Self {
         let cells = blank_grid(size);
         Self {
             size,
             cells,
-            empty_parking_cells: SetSampler::new(),
-            traversable_cells: SetSampler::new(),
-            empty_traversable_cells: SetSampler::new(),
+            empty_parking_cells: SetSampler::with_rng(RNG::from_rng(&mut *rng).unwrap()),
+            traversable_cells: SetSampler::with_rng(RNG::from_rng(&mut *rng).unwrap()),
+            empty_traversable_cells: SetSampler::with_rng(RNG::from_rng(&mut *rng).unwrap()),
         }
     }
 
@@ -408,6 +487,13 @@ impl Grid {
         }
     }
 
+    /// Makes the cell a road that can be driven through with the orientation of `c`.
+    pub fn draw_road(&mut self, c: &Coords) {
+        self.get_cell_mut(&c.xy).color = image::Rgb::from(COLOR_ROAD);
+        self.set_valid(c);
+        self.traversable_cells.insert(c.xy);
+    }
+
     fn add_traversable(&mut self, xy: &XYCell, direction: Orientations) {
         let cell = self.get_cell_mut(xy);
 
@@ -420,7 +506,7 @@ impl Grid {
         }
     }
 
-    pub fn random_available_coords(&self, rng: &mut ThreadRng) -> Coords {
+    pub fn random_available_coords(&self, rng: &mut RNG) -> Coords {
         if self.empty_traversable_cells.is_empty() {
             panic!("No traversable cells");
         }
@@ -436,7 +522,7 @@ impl Grid {
         }
     }
 
-    pub fn random_available_parking(&self, rng: &mut ThreadRng) -> Coords {
+    pub fn random_available_parking(&self, rng: &mut RNG) -> Coords {
         if self.empty_parking_cells.is_empty() {
             panic!("No empty parking cells");
         }
@@ -450,12 +536,7 @@ impl Grid {
     }
 
     pub fn make_parking_cell(&mut self, coords: &Coords) {
-        let mut cell = Cell::new();
-        cell.color = image::Rgb::from(COLOR_PARKING);
-        cell.is_parking = true;
-        cell.set_allowed(coords.orientation);
-        cell.ors[coords.orientation as usize].action_allowed[Actions::Backward as usize] = true;
-        self.replace_cell(&coords.xy, cell);
+        self.replace_cell(&coords.xy, Cell::parking(coords.orientation));
 
         self.set_valid(&coords);
 
@@ -463,6 +544,14 @@ impl Grid {
         self.set_valid(&previous);
     }
 
+    /// Turns the spot into a charging one, entered like a parking spot
+    pub fn make_charging_cell(&mut self, coords: &Coords) {
+        self.replace_cell(&coords.xy, Cell::charging(coords.orientation));
+
+        let previous = next_coords(coords, Actions::Backward);
+        self.set_valid(&previous);
+    }
+
     pub fn replace_cell(&mut self, xy: &XYCell, cell: Cell) {
         let prev_cell = self.get_cell_mut(xy);
 
@@ -495,10 +584,24 @@ impl Grid {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct World {
     pub grid: Grid,
     pub robots: Vec<Robot>,
+    pub config: ArbitrationConfig,
+    /// Total payments charged to each robot so far
+    pub payments: Vec<Payment>,
+    pub stats: ArbitrationStats,
+    /// The priority orders chosen at the previous step, by robot name
+    pub previous_orders: Vec<Vec<RobotName>>,
+    /// Results of the games already solved, if `config.use_cache` is set
+    pub cache: ArbCache,
+    /// Traffic signals restricting the robots on their stop lines
+    pub signals: Signals,
+    /// How the robots use and recover energy, if they do
+    pub battery: Option<BatteryModel>,
+    /// Roads closed at runtime
+    pub closures: Closures,
 }
 
 const RobotColors: [[u8; 3]; 7] = [
@@ -554,7 +657,8 @@ fn find_clusters(
         let nplayers = nplayers.min(MAX_GAME_SIZE - 1);
         games_by_size[nplayers] += 1;
 
-        let players = comp.iter().map(|x| graph[*x]).collect::<Vec<_>>();
+        // sorted, as the visiting order depends on how the edges were added
+        let players = comp.iter().map(|x| graph[*x]).sorted().collect::<Vec<_>>();
 
         let index2name = players.iter().map(|x| *x).collect::<Vec<_>>();
         let agents = index2name
@@ -591,12 +695,33 @@ impl World {
         self.place_robot(coords)
     }
     pub fn place_robot(&mut self, coords: Coords) -> usize {
-        let cell = self.grid.get_cell_mut(&coords.xy);
+        self.place_vehicle(coords, Vehicle::CAR)
+    }
+    /// The whole body is placed: folded into the cell in parking and charging spots,
+    /// in a line behind the head elsewhere.
+    pub fn place_vehicle(&mut self, coords: Coords, vehicle: Vehicle) -> usize {
         let robot_name = self.robots.len();
-        if cell.present.is_some() {
-            panic!("Cell {:?} already contains a robot", coords.xy);
+        let cell = self.grid.get_cell(&coords.xy);
+        let n = vehicle.length as usize - 1;
+        let trail = if cell.is_parking || cell.is_charging {
+            vec![coords.xy; n]
+        } else {
+            std::iter::successors(Some(coords), |c| Some(next_coords(c, Actions::Backward)))
+                .skip(1)
+                .take(n)
+                .map(|c| c.xy)
+                .collect_vec()
+        };
+        for xy in body_cells(&coords, &trail) {
+            if !self.grid.size.in_bounds(xy) {
+                panic!("Cell {:?} is outside the map", xy);
+            }
+            let cell = self.grid.get_cell_mut(&xy);
+            if cell.present.is_some() {
+                panic!("Cell {:?} already contains a robot", xy);
+            }
+            cell.present = Some(robot_name);
         }
-        cell.present = Some(robot_name);
 
         let i = (coords.xy.x + coords.xy.y) as usize % RobotColors.len();
         // sample random color
@@ -604,10 +729,47 @@ impl World {
         let robot = Robot {
             coords,
             color: image::Rgb::from(color),
+            priority: 0,
+            battery: self.battery.map_or(0, |b| b.capacity),
+            vehicle,
+            trail,
         };
         self.robots.push(robot);
+        self.payments.push(0);
         robot_name
     }
+    pub fn set_priority(&mut self, robot_name: usize, priority: PriorityClass) {
+        self.robots[robot_name].priority = priority;
+    }
+    /// Sets the battery model and fully charges all the robots
+    pub fn set_battery_model(&mut self, model: BatteryModel) {
+        self.battery = Some(model);
+        for robot in self.robots.iter_mut() {
+            robot.battery = model.capacity;
+        }
+    }
+    /// The charging cells, with the orientation in which robots park there
+    pub fn charging_cells(&self) -> Vec<Coords> {
+        self.grid
+            .iterate_cells()
+            .filter(|(_, cell)| cell.is_charging)
+            .filter_map(|(xy, cell)| {
+                let first = orientations_from_mask(cell.orientation_mask())
+                    .first()
+                    .copied();
+                first.map(|o| Coords::from(xy, o))
+            })
+            .collect_vec()
+    }
+    /// Closes part of the road; robots no longer enter it, except to leave the cell they are in.
+    /// Returns the coordinates newly closed, so that the agents crossing them can reroute.
+    pub fn close(&mut self, closure: Closure) -> Vec<Coords> {
+        self.closures.close(&mut self.grid, closure)
+    }
+    /// Reopens what the closure closed; returns the coordinates reopened.
+    pub fn reopen(&mut self, closure: Closure) -> Vec<Coords> {
+        self.closures.reopen(&mut self.grid, closure)
+    }
     pub fn place_random_robot_parking(&mut self, rng: &mut RNG) -> usize {
         let coords = self.grid.random_available_parking(rng);
         self.place_robot(coords)
@@ -629,7 +791,7 @@ impl World {
 
         let or = cell.ors[coords.orientation as usize];
         for i in 0..NUM_ACTIONS {
-            if or.action_allowed[i] {
+            if or.action_allowed[i] && self.signals.permits(coords, Actions::from_index(i), 0) {
                 all_actions.push(Actions::from_index(i));
             }
         }
@@ -654,28 +816,43 @@ impl World {
         }
         res
     }
+    /// Moves the head of the robot to `dest`, dragging its body along.
+    /// The whole vehicle folds into parking and charging spots.
     pub fn move_robot(&mut self, robot_name: usize, dest: Coords) {
-        let robot = self.robots.get_mut(robot_name).unwrap();
+        let robot = &self.robots[robot_name];
+        let dest_cell = self.grid.get_cell(&dest.xy);
+        let trail = match Actions::from_pair(&robot.coords, &dest) {
+            _ if dest_cell.is_parking || dest_cell.is_charging => {
+                vec![dest.xy; robot.trail.len()]
+            }
+            Some(action) => robot
+                .vehicle
+                .next_trail(&robot.coords, &robot.trail, action),
+            None => panic!("Robot {} cannot reach {:?} in one action", robot_name, dest),
+        };
+        let old_body = robot.body();
+        let new_body = body_cells(&dest, &trail);
 
-        if robot.coords.xy != dest.xy {
-            let old_cell = self.grid.get_cell_mut(&robot.coords.xy);
+        for xy in old_body.iter().filter(|xy| !new_body.contains(xy)) {
+            let old_cell = self.grid.get_cell_mut(xy);
             if old_cell.present != Some(robot_name) {
-                panic!("Robot {} is not in cell {:?}", robot_name, robot.coords.xy);
+                panic!("Robot {} is not in cell {:?}", robot_name, xy);
             }
             old_cell.present = None;
-            // old_cell.present.retain(|x| *x != a);
-            let cell = self.grid.get_cell_mut(&dest.xy);
+        }
+        for xy in new_body.iter().filter(|xy| !old_body.contains(xy)) {
+            let cell = self.grid.get_cell_mut(xy);
             if cell.present.is_some() {
-                panic!("Cell {:?} already contains a robot", dest.xy);
+                panic!("Cell {:?} already contains a robot", xy);
             }
             cell.present = Some(robot_name);
-
-            // assert!(cell.present.len() <= 1);
         }
 
+        let robot = &mut self.robots[robot_name];
         robot.coords = dest;
+        robot.trail = trail;
     }
-    pub fn step_robots(&mut self, f: &mut FNUpdate, rng: &mut RNG) {
+    pub fn step_robots(&mut self, f: &mut FNUpdate<'_>, rng: &mut RNG) {
         let nrobots = self.robots.len();
         let mut resource_usage: HashMap<(StepIndex, XYCell), HashSet<RobotName>> = HashMap::new();
 
@@ -686,34 +863,116 @@ impl World {
         let horizon = 5;
 
         for (a, robot) in self.robots.iter().enumerate() {
-            let plan = f(rng, a, robot, horizon);
+            let mut plan = f(rng, a, robot, horizon);
             assert_eq!(plan.len(), horizon);
+            if self.signals.restrict(robot.coords, &mut plan) {
+                self.stats.signal_stops += 1;
+            }
+            if self.closures.restrict(robot.coords, &mut plan) {
+                self.stats.closure_stops += 1;
+            }
+            if let Some(model) = self.battery {
+                if robot.battery == 0 && !self.grid.get_cell(&robot.coords.xy).is_charging {
+                    self.stats.stranded_steps += 1;
+                }
+                model.restrict(robot.battery, &mut plan);
+            }
 
-            let horizon_coords = simulate(robot.coords, &plan);
-            for (dt, c) in horizon_coords.iter().enumerate() {
-                let resource = (0, c.xy);
-                resource_usage.entry(resource).or_default().insert(a);
+            let mut last = robot.coords;
+            let mut trail = robot.trail.clone();
+            let mut cells = robot.body();
+            for action in &plan {
+                (last, trail) = robot.vehicle.after(&last, &trail, &[*action]);
+                cells.extend(body_cells(&last, &trail));
+            }
+            for xy in cells {
+                resource_usage.entry((0, xy)).or_default().insert(a);
             }
 
-            let aa = ArbAgent {
-                coord: robot.coords,
-                plan,
+            // robots pulling into parking leave the road
+            let end = if self.grid.get_cell(&last.xy).is_parking {
+                PlanEnd::Disappear
+            } else {
+                PlanEnd::Unknown
             };
+            let aa = ArbAgent::new(robot.coords, plan)
+                .with_end(end)
+                .with_priority(robot.priority)
+                .with_vehicle(robot.vehicle, robot.trail.clone());
             players_plans.push(aa);
         }
 
         let games = find_clusters(&players_plans, &resource_usage);
 
-        let max_permutations = 100000;
-        let mut actions = Vec::with_capacity(nrobots);
-        for i in 0..nrobots {
-            actions.push(Actions::Wait);
-        }
+        let budget = self.config.budget;
+        // the closest equivalent for the searches that only take a number of orders,
+        // which stay capped when only a time budget is given
+        let max_permutations = budget.max_nodes();
+        // the actions of the first step of each robot
+        let mut actions = vec![vec![Actions::Wait]; nrobots];
 
+        let ranks = ranks_from_orders(&self.previous_orders);
+        let mut orders = Vec::new();
         for eg in &games {
             let setup = &eg.setup;
             let index2name = &eg.index2name;
-            let arb_result0 = find_feasible_plans(&setup, max_permutations);
+            let mixed = !setup.agents.iter().map(|a| a.priority).all_equal();
+            // the exact search and the analyses only know robots of one cell and one move per step
+            let unit = setup.agents.iter().all(|a| a.vehicle.is_unit());
+            let hierarchical = match self.config.hierarchical_threshold {
+                Some(threshold) if setup.agents.len() > threshold => {
+                    let grid = &self.grid;
+                    let block_of = |xy: &XYCell| {
+                        if grid.size.in_bounds(*xy) {
+                            grid.get_cell(xy).block
+                        } else {
+                            None
+                        }
+                    };
+                    let result = find_hierarchical_plan(setup, block_of, max_permutations);
+                    self.stats.hierarchical_games += 1;
+                    if result.solution.is_none() {
+                        self.stats.hierarchical_fallbacks += 1;
+                    }
+                    result.solution
+                }
+                _ => None,
+            };
+            let model = self.config.cost_model;
+            let (arb_result0, complete) = if let Some(solution) = hierarchical {
+                let mut result = ArbResult::empty();
+                result.add(evaluate(&model, setup, &solution), &solution);
+                (result, true)
+            } else if self.config.stackelberg && mixed {
+                let result = find_stackelberg_plans_with(setup, max_permutations, model);
+                self.stats.stackelberg_games += 1;
+                self.stats.leader_gain += result.leader_gain;
+                (result.outcomes, result.complete)
+            } else if self.config.use_cache {
+                self.cache.solve(setup, |c| {
+                    find_feasible_plans_anytime_with(c, budget, model)
+                })
+            } else {
+                find_feasible_plans_anytime_with(setup, budget, model)
+            };
+            if !complete {
+                self.stats.incomplete_searches += 1;
+            }
+            // the exact fronts are on the waits
+            let waits = model == CostKind::Waits;
+            if let Some(max_players) = self.config.compare_exact.filter(|_| unit && waits) {
+                let nplayers = setup.agents.len();
+                if nplayers > 1 && nplayers <= max_players {
+                    let exact = find_exact_plans(setup, default_max_delay(setup));
+                    let cmp = compare_fronts(&arb_result0, &exact);
+                    self.stats.exact_compared += 1;
+                    if cmp.missed_points > 0 {
+                        self.stats.exact_incomplete += 1;
+                    }
+                    self.stats.exact_missed_points += cmp.missed_points;
+                    self.stats.exact_social_cost_gap += cmp.social_cost_gap;
+                }
+            }
             let arb_result = arb_result0.remove_redundant(rng);
             if arb_result.solutions.len() > 1 {
                 eprintln!("game: {:?}", setup);
@@ -721,52 +980,135 @@ impl World {
                 eprintln!("result: {:?}", arb_result);
             }
 
-            if arb_result.solutions.is_empty() {
-                eprintln!(
-                    "!! empty solutions for game with {} players",
-                    setup.agents.len()
-                );
+            let committed = match self.config.commitment {
+                // the payments are only meaningful at the outcome of smallest total cost
+                _ if self.config.vcg_payments => None,
+                CommitmentPolicy::Renegotiate => None,
+                policy => committed_order(&ranks, index2name)
+                    .and_then(|perm| assign(setup, &perm))
+                    .map(|(_, solution)| solution)
+                    .filter(|solution| {
+                        let costs = evaluate(&model, setup, solution);
+                        keep_commitment(policy, &costs, &arb_result0)
+                    }),
+            };
+            if committed.is_some() {
+                self.stats.commitments_kept += 1;
+            }
 
-                for name in index2name {
-                    actions[*name] = Actions::Wait;
-                }
+            let chosen = if committed.is_some() {
+                committed
+            } else if arb_result.solutions.is_empty() {
+                None
+            } else if self.config.vcg_payments {
+                Some(pick_efficient(&arb_result, rng))
             } else {
-                let solution = arb_result.pick_one(rng);
+                Some(arb_result.pick_one(rng))
+            };
+
+            match chosen {
+                None => {
+                    eprintln!(
+                        "!! empty solutions for game with {} players",
+                        setup.agents.len()
+                    );
+
+                    for name in index2name {
+                        actions[*name] = vec![Actions::Wait];
+                    }
+                }
+                Some(solution) => {
+                    if setup.agents.len() > 1 {
+                        self.stats.games += 1;
+                        if self.config.check_stability && unit {
+                            self.stats.stability_checked += 1;
+                            if !is_nash_stable(setup, &solution) {
+                                self.stats.unstable += 1;
+                            }
+                        }
+                        let order = solution.perm.iter().map(|i| index2name[*i]).collect_vec();
+                        let (reversals, pairs) = count_order_reversals(&ranks, &order);
+                        self.stats.order_reversals += reversals;
+                        self.stats.order_pairs += pairs;
+                        orders.push(order);
+                    }
 
-                for (i, name) in solution.robots.iter().enumerate() {
-                    let name = index2name[i];
-                    let action = solution.robots[i].plan[0];
-                    actions[name] = action;
+                    if self.config.vcg_payments && setup.agents.len() > 1 {
+                        let payments = vcg_payments(setup, &solution.costs, max_permutations);
+                        for (i, payment) in payments.iter().enumerate() {
+                            self.payments[index2name[i]] += payment;
+                        }
+                    }
+
+                    if let Some(i) = self
+                        .config
+                        .explain_robot
+                        .filter(|_| unit)
+                        .and_then(|r| index2name.iter().position(|name| *name == r))
+                    {
+                        let explanations = explain(setup, &solution);
+                        let chain = blocking_chain(&explanations, i, 0);
+                        if !chain.is_empty() {
+                            eprintln!("{}", format_blocking_chain(&chain, index2name));
+                        }
+                    }
+
+                    for (i, name) in solution.robots.iter().enumerate() {
+                        let name = index2name[i];
+                        // robots that already disappeared have nothing left to do
+                        let step = setup.agents[i]
+                            .vehicle
+                            .plan_steps(&solution.robots[i].plan)
+                            .first()
+                            .map_or(vec![Actions::Wait], |step| step.to_vec());
+                        actions[name] = step;
+                    }
                 }
             }
         }
+        self.previous_orders = orders;
         let mut indices: Vec<usize> = (0..nrobots).collect();
         indices.shuffle(rng);
+        let mut executed = vec![vec![Actions::Wait]; nrobots];
 
         for a in indices {
-            let action = actions[a];
-
-            let robot = &self.robots[a];
-
-            let nex = next_coords(&robot.coords, action);
-
-            let cell = self.grid.get_cell_mut(&robot.coords.xy);
-
-            match cell.present {
-                None => {}
-                Some(robot_name) => {
-                    if robot_name == a {
-                        // panic!("Robot {} is not in cell {:?}", a, robot.coords.xy);
-                    } else {
-                        eprintln!("Robot {} cannot go to  cell {:?}", a, cell);
-
-                        // nex = robot.coords;
-                        continue;
-                    }
+            let step = &actions[a];
+            let mut done = 0;
+            for action in step {
+                let robot = &self.robots[a];
+                let (nex, trail) = robot.vehicle.after(&robot.coords, &robot.trail, &[*action]);
+                // the head, or the tail when backing up, may enter a cell not yet vacated
+                let blocked = body_cells(&nex, &trail).into_iter().find(|xy| {
+                    !self.grid.size.in_bounds(*xy)
+                        || matches!(self.grid.get_cell(xy).present, Some(r) if r != a)
+                });
+                if let Some(xy) = blocked {
+                    eprintln!("Robot {} cannot go to  cell {:?}", a, xy);
+                    break;
+                }
+                self.move_robot(a, nex);
+                done += 1;
+            }
+            if done > 0 {
+                executed[a] = step[..done].to_vec();
+            }
+        }
+        if let Some(model) = self.battery {
+            for (robot, step) in self.robots.iter_mut().zip(executed) {
+                let on_charger = self.grid.get_cell(&robot.coords.xy).is_charging;
+                let before = robot.battery;
+                robot.battery = step.iter().fold(before, |charge, action| {
+                    model.after(charge, *action, on_charger)
+                });
+                if on_charger && step == [Actions::Wait] && before < model.capacity {
+                    self.stats.charging_steps += 1;
+                }
+                if before > 0 && robot.battery == 0 && !on_charger {
+                    self.stats.strandings += 1;
                 }
             }
-            self.move_robot(a, nex);
         }
+        self.signals.advance(&self.robots);
         //
         //
         // // random permutation of 0, n
@@ -836,6 +1178,7 @@ pub fn next_coords(coord: &Coords, action: Actions) -> Coords {
 mod test {
     // add test
     use super::*;
+    use crate::{Block, BlockMap};
 
     #[test]
     pub fn test_1() {
@@ -862,9 +1205,54 @@ mod test {
         }
         assert_eq!(initial, orientation);
     }
+
+    fn seeded_run(seed: u64, nsteps: usize) -> Vec<Vec<Coords>> {
+        let mut rng = rng_from_seed(seed);
+        let map_size = XY::new(4, 3);
+        let block_size = XY::new(16, 16);
+        let mut bl = BlockMap::new(map_size, block_size);
+        for p in map_size.iterate_xy_interior() {
+            bl.set_block(p, Block::with_parking(block_size, 2, &mut rng));
+        }
+        let mut world = World::new(bl.stitch(&mut rng));
+        let parkings = world
+            .grid
+            .empty_parking_cells
+            .queue
+            .clone()
+            .into_sorted_vec();
+        for xy in parkings.iter().take(8) {
+            let orientation = world.grid.get_cell(xy).random_direction(&mut rng);
+            world.place_robot(Coords::from(*xy, orientation));
+        }
+        let snapshot = world.clone();
+        let mut f = move |rng: &mut RNG, _name: usize, robot: &Robot, horizon: usize| {
+            let mut coords = robot.coords;
+            (0..horizon)
+                .map(|_| {
+                    let actions = snapshot.allowed_robot_actions_if_empty(&coords);
+                    let action = *actions.choose(rng).unwrap();
+                    coords = next_coords(&coords, action);
+                    action
+                })
+                .collect_vec()
+        };
+        let mut trajectory = Vec::new();
+        for _ in 0..nsteps {
+            world.step_robots(&mut f, &mut rng);
+            trajectory.push(world.robots.iter().map(|r| r.coords).collect());
+        }
+        trajectory
+    }
+
+    #[test]
+    pub fn test_same_seed_same_run() {
+        assert_eq!(seeded_run(7, 20), seeded_run(7, 20));
+        assert_ne!(seeded_run(7, 20), seeded_run(8, 20));
+    }
 }
 
-#[derive(Hash, Eq, PartialEq, Copy, Clone)]
+#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Serialize, Deserialize)]
 pub enum Actions {
     Wait = 0,
     Forward = 1,
@@ -918,7 +1306,7 @@ impl Actions {
     }
 }
 
-pub type FNUpdate = dyn FnMut(&mut RNG, usize, &Robot, usize) -> Vec<Actions>;
+pub type FNUpdate<'a> = dyn FnMut(&mut RNG, usize, &Robot, usize) -> Vec<Actions> + 'a;
 
 pub fn blank_grid(size: XY<i16>) -> Vec<Vec<Cell>> {
     let mut grid = Vec::new();
@@ -938,14 +1326,22 @@ pub fn blank_grid(size: XY<i16>) -> Vec<Vec<Cell>> {
 impl World {
     pub fn new(grid: Grid) -> Self {
         let robots = Vec::new();
-        Self { grid, robots }
+        Self {
+            grid,
+            robots,
+            config: ArbitrationConfig::default(),
+            payments: Vec::new(),
+            stats: ArbitrationStats::default(),
+            previous_orders: Vec::new(),
+            cache: ArbCache::new(),
+            signals: Signals::default(),
+            battery: None,
+            closures: Closures::default(),
+        }
     }
     pub fn blank(size: Size) -> Self {
         let grid = Grid::new(size);
-        World {
-            grid,
-            robots: Vec::new(),
-        }
+        World::new(grid)
     }
 }
 
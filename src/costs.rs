@@ -0,0 +1,303 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+
+use itertools::Itertools;
+use num::ToPrimitive;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    assign, get_permutations, next_coords, Actions, ArbAgent, ArbResult, ArbSetup, ArbSolution,
+    Cost, Grid, XYCell,
+};
+
+/// How much an agent pays for following a plan.
+///
+/// The fronts compare the values agent by agent, so they are only partially ordered.
+/// The values key the fronts, so they are hashable; floats are wrapped in `Real`.
+pub trait CostModel {
+    type Value: Clone + Ord + Hash + Debug;
+
+    /// Cost for agent number `index` of the game to follow `plan` instead of `agent.plan`.
+    fn cost(&self, index: usize, agent: &ArbAgent, plan: &[Actions]) -> Self::Value;
+}
+
+/// A real cost, totally ordered with `f64::total_cmp` so that it can key a front
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Real(pub f64);
+
+impl PartialEq for Real {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Real {}
+
+impl PartialOrd for Real {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Real {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl Hash for Real {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+/// The number of waits, as in `RobotResult::cost`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WaitCount;
+
+impl CostModel for WaitCount {
+    type Value = Cost;
+
+    fn cost(&self, _index: usize, _agent: &ArbAgent, plan: &[Actions]) -> Cost {
+        plan.iter().filter(|a| **a == Actions::Wait).count()
+    }
+}
+
+/// The time at which the last action that is not a wait is finished
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ArrivalTime;
+
+impl CostModel for ArrivalTime {
+    type Value = Cost;
+
+    fn cost(&self, _index: usize, _agent: &ArbAgent, plan: &[Actions]) -> Cost {
+        plan.iter()
+            .rposition(|a| *a != Actions::Wait)
+            .map_or(0, |t| t + 1)
+    }
+}
+
+/// Waits whose cost depends on the cell where the agent waits
+#[derive(Debug, Clone, Default)]
+pub struct WeightedWaits {
+    pub default_weight: f64,
+    pub weights: HashMap<XYCell, f64>,
+}
+
+impl WeightedWaits {
+    /// Waiting in an intersection (a cell allowing more than one orientation)
+    /// costs `intersection_weight`; anywhere else `lane_weight`.
+    pub fn intersections(grid: &Grid, intersection_weight: f64, lane_weight: f64) -> Self {
+        let weights = grid
+            .iterate_cells()
+            .filter(|(_, cell)| cell.ors.iter().filter(|o| o.robot_allowed).count() > 1)
+            .map(|(xy, _)| (xy, intersection_weight))
+            .collect();
+        Self {
+            default_weight: lane_weight,
+            weights,
+        }
+    }
+}
+
+impl CostModel for WeightedWaits {
+    type Value = Real;
+
+    fn cost(&self, _index: usize, agent: &ArbAgent, plan: &[Actions]) -> Real {
+        let mut coord = agent.coord;
+        let mut total = 0.0;
+        for action in plan {
+            if *action == Actions::Wait {
+                total += self
+                    .weights
+                    .get(&coord.xy)
+                    .copied()
+                    .unwrap_or(self.default_weight);
+            }
+            coord = next_coords(&coord, *action);
+        }
+        Real(total)
+    }
+}
+
+/// Energy spent in stop-and-go: the number of times the agent starts again after waiting
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StopAndGo;
+
+impl CostModel for StopAndGo {
+    type Value = Cost;
+
+    fn cost(&self, _index: usize, _agent: &ArbAgent, plan: &[Actions]) -> Cost {
+        plan.iter()
+            .tuple_windows()
+            .filter(|(a, b)| **a == Actions::Wait && **b != Actions::Wait)
+            .count()
+    }
+}
+
+/// Another cost model scaled by a weight for each agent of the game;
+/// the agents without a weight count once.
+#[derive(Debug, Clone)]
+pub struct PriorityWeighted<M> {
+    pub inner: M,
+    pub weights: Vec<f64>,
+}
+
+impl<M> CostModel for PriorityWeighted<M>
+where
+    M: CostModel,
+    M::Value: ToPrimitive,
+{
+    type Value = Real;
+
+    fn cost(&self, index: usize, agent: &ArbAgent, plan: &[Actions]) -> Real {
+        let c = self.inner.cost(index, agent, plan).to_f64().unwrap();
+        Real(self.weights.get(index).copied().unwrap_or(1.0) * c)
+    }
+}
+
+/// Compares first by the first model, then by the second
+#[derive(Debug, Clone, Default)]
+pub struct Lexicographic<A, B>(pub A, pub B);
+
+impl<A: CostModel, B: CostModel> CostModel for Lexicographic<A, B> {
+    type Value = (A::Value, B::Value);
+
+    fn cost(&self, index: usize, agent: &ArbAgent, plan: &[Actions]) -> Self::Value {
+        (
+            self.0.cost(index, agent, plan),
+            self.1.cost(index, agent, plan),
+        )
+    }
+}
+
+/// The costs of all the agents in a solution
+pub fn evaluate<M: CostModel>(model: &M, s: &ArbSetup, solution: &ArbSolution) -> Vec<M::Value> {
+    s.agents
+        .iter()
+        .enumerate()
+        .map(|(i, agent)| model.cost(i, agent, &solution.robots[i].plan))
+        .collect_vec()
+}
+
+/// The cost models that can be chosen in `ArbitrationConfig`: the ones counting steps,
+/// so that the simulator keeps its fronts in `ArbResult`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum CostKind {
+    #[default]
+    Waits,
+    Arrival,
+    StopAndGo,
+}
+
+impl CostKind {
+    /// "waits", "arrival" or "stop-and-go"
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "waits" => Some(CostKind::Waits),
+            "arrival" => Some(CostKind::Arrival),
+            "stop-and-go" => Some(CostKind::StopAndGo),
+            _ => None,
+        }
+    }
+}
+
+impl CostModel for CostKind {
+    type Value = Cost;
+
+    fn cost(&self, index: usize, agent: &ArbAgent, plan: &[Actions]) -> Cost {
+        match self {
+            CostKind::Waits => WaitCount.cost(index, agent, plan),
+            CostKind::Arrival => ArrivalTime.cost(index, agent, plan),
+            CostKind::StopAndGo => StopAndGo.cost(index, agent, plan),
+        }
+    }
+}
+
+/// Like `find_feasible_plans`, but the front is computed for the given cost model.
+pub fn find_feasible_plans_with<M: CostModel>(
+    s: &ArbSetup,
+    max: usize,
+    model: &M,
+) -> ArbResult<M::Value> {
+    let mut result = ArbResult::empty();
+    for perm in get_permutations(s.agents.len(), max) {
+        if let Some((_, solution)) = assign(s, &perm) {
+            result.add(evaluate(model, s, &solution), &solution);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use itertools::Itertools;
+
+    use crate::*;
+
+    const F: Actions = Actions::Forward;
+    const W: Actions = Actions::Wait;
+
+    fn crossing() -> ArbSetup {
+        let agents = vec![
+            ArbAgent::new(
+                Coords::from(XYCell::new(1, 0), Orientations::WEST),
+                vec![F; 3],
+            ),
+            ArbAgent::new(
+                Coords::from(XYCell::new(0, -1), Orientations::NORTH),
+                vec![F; 3],
+            ),
+        ];
+        ArbSetup { agents }
+    }
+
+    #[test]
+    fn test_cost_models() {
+        let agent = &crossing().agents[0];
+        let plan = [W, F, W, W, F, W];
+        assert_eq!(WaitCount.cost(0, agent, &plan), 4);
+        assert_eq!(ArrivalTime.cost(0, agent, &plan), 5);
+        assert_eq!(StopAndGo.cost(0, agent, &plan), 2);
+        let weighted = WeightedWaits {
+            default_weight: 1.0,
+            weights: maplit::hashmap! {XYCell::new(0, 0) => 10.0},
+        };
+        // the first wait is at (1,0), the next two at (0,0), the last one at (-1,0)
+        assert_eq!(weighted.cost(0, agent, &plan), Real(22.0));
+        let unweighted = PriorityWeighted {
+            inner: WaitCount,
+            weights: Vec::new(),
+        };
+        assert_eq!(unweighted.cost(0, agent, &plan), Real(4.0));
+        let lex = Lexicographic(ArrivalTime, WaitCount);
+        assert!(lex.cost(0, agent, &[F, W]) < lex.cost(0, agent, &[W, F]));
+    }
+
+    #[test]
+    fn test_front_with_models() {
+        let setup = crossing();
+        let counts = find_feasible_plans_with(&setup, 0, &WaitCount);
+        assert_eq!(counts, find_feasible_plans(&setup, 0));
+
+        // the configurable models key the fronts of the anytime search by their own costs
+        let (arrivals, complete) =
+            find_feasible_plans_anytime_with(&setup, Budget::unlimited(), CostKind::Arrival);
+        assert!(complete);
+        for (costs, solutions) in &arrivals.solutions {
+            for solution in solutions {
+                assert_eq!(*costs, evaluate(&ArrivalTime, &setup, solution));
+            }
+        }
+
+        // a heavy weight on the first agent does not change who is efficient
+        let weighted = PriorityWeighted {
+            inner: WaitCount,
+            weights: vec![10.0, 1.0],
+        };
+        let front = find_feasible_plans_with(&setup, 0, &weighted);
+        assert_eq!(front.solutions.len(), 2);
+    }
+}
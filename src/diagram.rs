@@ -0,0 +1,186 @@
+use image::{Rgb, RgbImage};
+use imageproc::drawing::{draw_filled_rect_mut, draw_hollow_rect_mut};
+use imageproc::rect::Rect;
+use itertools::Itertools;
+
+use crate::{simulate, Actions, ArbSetup, ArbSolution, Coords, PlanEnd, XYCell};
+
+const AGENT_COLORS: [[u8; 3]; 8] = [
+    [230, 25, 75],
+    [60, 180, 75],
+    [0, 130, 200],
+    [245, 130, 48],
+    [145, 30, 180],
+    [70, 240, 240],
+    [240, 50, 230],
+    [210, 245, 60],
+];
+const COLOR_BACKGROUND: Rgb<u8> = Rgb([40, 40, 40]);
+const COLOR_SEPARATOR: Rgb<u8> = Rgb([0, 0, 0]);
+/// Frame around the agents that wait at that time step
+pub const COLOR_WAIT: Rgb<u8> = Rgb([255, 255, 255]);
+
+pub fn agent_color(agent: usize) -> Rgb<u8> {
+    Rgb(AGENT_COLORS[agent % AGENT_COLORS.len()])
+}
+
+fn darker(c: Rgb<u8>) -> Rgb<u8> {
+    Rgb([c[0] / 3, c[1] / 3, c[2] / 3])
+}
+
+/// The cells covered by the diagram: the bounding box of all the paths, with a margin of one.
+fn region(paths: &[Vec<Coords>]) -> (XYCell, XYCell) {
+    let xys = paths.iter().flatten().map(|c| c.xy).collect_vec();
+    let min = XYCell::new(
+        xys.iter().map(|xy| xy.x).min().unwrap_or(0) - 1,
+        xys.iter().map(|xy| xy.y).min().unwrap_or(0) - 1,
+    );
+    let max = XYCell::new(
+        xys.iter().map(|xy| xy.x).max().unwrap_or(0) + 1,
+        xys.iter().map(|xy| xy.y).max().unwrap_or(0) + 1,
+    );
+    (min, max)
+}
+
+/// Space-time diagram of a solution.
+///
+/// The first panel shows the paths of the agents over the local region (starting cells in
+/// full color); then there is one panel per time step with the cells occupied at that time.
+/// The agents that wait are framed with `COLOR_WAIT`; a dark mark shows the heading.
+/// The agents that disappear are no longer drawn after their last action.
+/// Each cell is `cell_px` pixels wide.
+pub fn render_space_time(s: &ArbSetup, sol: &ArbSolution, cell_px: u32) -> RgbImage {
+    let paths = s
+        .agents
+        .iter()
+        .zip(sol.robots.iter())
+        .map(|(a, r)| simulate(a.coord, &r.plan))
+        .collect_vec();
+    let (min, max) = region(&paths);
+    let w = (max.x - min.x + 1) as u32;
+    let h = (max.y - min.y + 1) as u32;
+    let nsteps = paths.iter().map(|p| p.len()).max().unwrap_or(1);
+    let panel_w = w * cell_px + 1;
+    let mut img =
+        RgbImage::from_pixel(panel_w * (nsteps as u32 + 1), h * cell_px, COLOR_BACKGROUND);
+
+    let cell_rect = |panel: usize, xy: &XYCell| {
+        let u = panel as u32 * panel_w + (xy.x - min.x) as u32 * cell_px;
+        let v = (max.y - xy.y) as u32 * cell_px;
+        Rect::at(u as i32, v as i32).of_size(cell_px, cell_px)
+    };
+
+    // the paths
+    for (a, path) in paths.iter().enumerate() {
+        for c in path {
+            draw_filled_rect_mut(&mut img, cell_rect(0, &c.xy), darker(agent_color(a)));
+        }
+        draw_filled_rect_mut(&mut img, cell_rect(0, &path[0].xy), agent_color(a));
+    }
+    // one panel per time step
+    for t in 0..nsteps {
+        let panel = t + 1;
+        for (a, path) in paths.iter().enumerate() {
+            if t >= path.len() && s.agents[a].end == PlanEnd::Disappear {
+                continue;
+            }
+            let c = path[t.min(path.len() - 1)];
+            let rect = cell_rect(panel, &c.xy);
+            draw_filled_rect_mut(&mut img, rect, agent_color(a));
+            if sol.robots[a].plan.get(t) == Some(&Actions::Wait) {
+                draw_hollow_rect_mut(&mut img, rect, COLOR_WAIT);
+            }
+            // heading mark on the side of the cell the agent faces
+            let mark = (cell_px / 4).max(1);
+            let v = c.orientation.vector();
+            let cx = rect.left() + (cell_px / 2) as i32 + v.x as i32 * (cell_px / 4) as i32;
+            let cy = rect.top() + (cell_px / 2) as i32 - v.y as i32 * (cell_px / 4) as i32;
+            let mark_rect =
+                Rect::at(cx - (mark / 2) as i32, cy - (mark / 2) as i32).of_size(mark, mark);
+            draw_filled_rect_mut(&mut img, mark_rect, darker(agent_color(a)));
+        }
+    }
+    for panel in 1..=nsteps as u32 {
+        let u = panel * panel_w - 1;
+        draw_filled_rect_mut(
+            &mut img,
+            Rect::at(u as i32, 0).of_size(1, h * cell_px),
+            COLOR_SEPARATOR,
+        );
+    }
+    img
+}
+
+/// Renders the diagram and saves it; the format is given by the extension.
+pub fn save_space_time(
+    s: &ArbSetup,
+    sol: &ArbSolution,
+    cell_px: u32,
+    filename: &str,
+) -> Result<(), String> {
+    render_space_time(s, sol, cell_px)
+        .save(filename)
+        .map_err(|e| format!("cannot save {filename}: {e}"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::COLOR_BACKGROUND;
+    use crate::*;
+
+    const F: Actions = Actions::Forward;
+
+    #[test]
+    fn test_render_crossing() {
+        let agents = vec![
+            ArbAgent::new(
+                Coords::from(XYCell::new(1, 0), Orientations::WEST),
+                vec![F; 3],
+            ),
+            ArbAgent::new(
+                Coords::from(XYCell::new(0, -1), Orientations::NORTH),
+                vec![F; 3],
+            ),
+        ];
+        let setup = ArbSetup { agents };
+        let (_, sol) = assign(&setup, &vec![0, 1]).unwrap();
+        let cell_px = 8;
+        let img = render_space_time(&setup, &sol, cell_px);
+        // region from (-3, -2) to (2, 3), and one panel per time step after the paths
+        let nsteps = sol.robots[1].plan.len() + 1;
+        assert_eq!(img.height(), 6 * cell_px);
+        assert_eq!(img.width(), (6 * cell_px + 1) * (nsteps as u32 + 1));
+        // the second agent waits at t=0 in its starting cell (0, -1)
+        assert_eq!(sol.robots[1].plan[0], Actions::Wait);
+        let u = (6 * cell_px + 1) + 3 * cell_px;
+        let v = 4 * cell_px;
+        assert_eq!(*img.get_pixel(u, v), COLOR_WAIT);
+        assert_eq!(*img.get_pixel(u + 1, v + 1), agent_color(1));
+    }
+
+    #[test]
+    fn test_disappeared_agent_not_drawn() {
+        // the first agent leaves the road after one step; the second one drives on
+        let agents = vec![
+            ArbAgent::new(Coords::from(XYCell::new(0, 0), Orientations::EAST), vec![F])
+                .with_end(PlanEnd::Disappear),
+            ArbAgent::new(
+                Coords::from(XYCell::new(0, 3), Orientations::EAST),
+                vec![F; 3],
+            ),
+        ];
+        let setup = ArbSetup { agents };
+        let (_, sol) = assign(&setup, &vec![0, 1]).unwrap();
+        let cell_px = 4;
+        let img = render_space_time(&setup, &sol, cell_px);
+        // region from (-1, -1) to (4, 4)
+        let panel_w = 6 * cell_px + 1;
+        let pixel = |t: u32, x: u32, y: u32| {
+            *img.get_pixel((t + 1) * panel_w + (x + 1) * cell_px, (4 - y) * cell_px)
+        };
+        // at its final cell on arrival, then gone
+        assert_eq!(pixel(1, 1, 0), agent_color(0));
+        assert_eq!(pixel(2, 1, 0), COLOR_BACKGROUND);
+        assert_eq!(pixel(3, 3, 3), agent_color(1));
+    }
+}
@@ -1,11 +1,12 @@
 use std::collections::HashSet;
 use std::hash::Hash;
 
-use crate::RNG;
+use crate::{rng_from_seed, DEFAULT_SEED, RNG};
 use priority_queue::PriorityQueue;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SetSampler<T>
 where
     T: Hash + Eq + Copy + Clone,
@@ -20,8 +21,12 @@ where
     T: Hash + Eq + Copy,
 {
     pub fn new() -> Self {
+        Self::with_rng(rng_from_seed(DEFAULT_SEED))
+    }
+
+    pub fn with_rng(rng: RNG) -> Self {
         Self {
-            rng: RNG::default(),
+            rng,
             contents: HashSet::new(),
             queue: PriorityQueue::new(),
         }
@@ -0,0 +1,253 @@
+use itertools::Itertools;
+
+use crate::{
+    is_action_feasible, leq, mark_occupied, occupied_by_someone_else, plan_horizon, reserve_end,
+    social_cost, Actions, ArbResult, ArbSetup, ArbSolution, Coords, Cost, Costs, Plan, PlanEnd,
+    RobotResult, RSM,
+};
+
+/// Partial joint schedule explored by the exact search
+#[derive(Debug, Clone)]
+struct JointNode {
+    t: usize,
+    /// for each agent, the number of its nominal actions already executed
+    progress: Vec<usize>,
+    coords: Vec<Coords>,
+    plans: Vec<Plan>,
+    costs: Costs,
+    resources: RSM,
+}
+
+/// The largest delay worth considering: each agent may wait for all the others to pass.
+pub fn default_max_delay(s: &ArbSetup) -> usize {
+    s.agents.iter().map(|a| a.plan.len()).sum()
+}
+
+/// Finds the exact Pareto front by searching the joint schedules directly:
+/// at each step, every agent either executes its next action or waits.
+/// Only one schedule is kept for each cost vector.
+/// Exponential in the number of agents: use it for small games only.
+pub fn find_exact_plans(s: &ArbSetup, max_delay: usize) -> ArbResult {
+    let n = s.agents.len();
+    let mut resources: RSM = Default::default();
+    for (a, agent) in s.agents.iter().enumerate() {
+        mark_occupied(&mut resources, 0, &agent.coord.xy, a);
+    }
+    let horizon = plan_horizon(s);
+    for (a, agent) in s.agents.iter().enumerate() {
+        if agent.nominal_plan().is_empty()
+            && !reserve_end(&mut resources, a, agent.end, 0, &agent.coord.xy, horizon)
+        {
+            return ArbResult::empty();
+        }
+    }
+    let root = JointNode {
+        t: 0,
+        progress: vec![0; n],
+        coords: s.agents.iter().map(|a| a.coord).collect_vec(),
+        plans: vec![Plan::new(); n],
+        costs: vec![0; n],
+        resources,
+    };
+    let mut result = ArbResult::empty();
+    expand(s, &root, max_delay, &mut result);
+    result
+}
+
+fn expand(s: &ArbSetup, node: &JointNode, max_delay: usize, result: &mut ArbResult) {
+    // costs only grow: nothing to find below a node that is already matched
+    if result.solutions.keys().any(|c| leq(c, &node.costs)) {
+        return;
+    }
+    let active = (0..s.agents.len())
+        .filter(|i| node.progress[*i] < s.agents[*i].nominal_plan().len())
+        .collect_vec();
+    if active.is_empty() {
+        result.add_solution(&joint_solution(node));
+        return;
+    }
+    // try the moves with fewer waiting agents first, so that pruning kicks in early
+    let choices = (0..(1usize << active.len())).sorted_by_key(|m| m.count_zeros());
+    for movers in choices {
+        if let Some(child) = joint_step(s, node, &active, movers, max_delay) {
+            expand(s, &child, max_delay, result);
+        }
+    }
+}
+
+fn joint_step(
+    s: &ArbSetup,
+    node: &JointNode,
+    active: &[usize],
+    movers: usize,
+    max_delay: usize,
+) -> Option<JointNode> {
+    let horizon = plan_horizon(s);
+    if node.t >= horizon && s.agents.iter().any(|a| a.end == PlanEnd::Stay) {
+        // beyond the horizon the cells of the agents that stay are not reserved
+        return None;
+    }
+    let mut child = node.clone();
+    for (k, i) in active.iter().enumerate() {
+        let i = *i;
+        if movers & (1 << k) != 0 {
+            let agent = &s.agents[i];
+            let plan = agent.nominal_plan();
+            let action = plan[node.progress[i]];
+            let (coord2, r) =
+                is_action_feasible(&child.resources, i, &node.coords[i], node.t, action)?;
+            child.resources = r;
+            child.coords[i] = coord2;
+            child.progress[i] += 1;
+            child.plans[i].push(action);
+            if action == Actions::Wait {
+                child.costs[i] += 1;
+            }
+            if child.progress[i] == plan.len()
+                && !reserve_end(
+                    &mut child.resources,
+                    i,
+                    agent.end,
+                    node.t + 1,
+                    &coord2.xy,
+                    horizon,
+                )
+            {
+                return None;
+            }
+        } else {
+            let xy = node.coords[i].xy;
+            if child.costs[i] >= max_delay
+                || occupied_by_someone_else(&child.resources, node.t, &xy, i)
+            {
+                return None;
+            }
+            mark_occupied(&mut child.resources, node.t, &xy, i);
+            child.plans[i].push(Actions::Wait);
+            child.costs[i] += 1;
+        }
+    }
+    child.t += 1;
+    Some(child)
+}
+
+fn joint_solution(node: &JointNode) -> ArbSolution {
+    // there is no priority order: report the agents by completion time
+    let perm = (0..node.plans.len())
+        .sorted_by_key(|i| node.plans[*i].len())
+        .collect_vec();
+    let robots = node
+        .plans
+        .iter()
+        .zip(node.costs.iter())
+        .map(|(plan, cost)| RobotResult {
+            plan: plan.clone(),
+            cost: *cost,
+        })
+        .collect_vec();
+    ArbSolution {
+        perm,
+        costs: node.costs.clone(),
+        robots,
+        payments: None,
+    }
+}
+
+/// How the front found by the priority heuristic compares to the exact one
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FrontComparison {
+    pub heuristic_points: usize,
+    pub exact_points: usize,
+    /// Exact front points that the heuristic did not find
+    pub missed_points: usize,
+    /// Best total cost found by the heuristic minus the optimal total cost
+    pub social_cost_gap: Cost,
+}
+
+pub fn compare_fronts(heuristic: &ArbResult, exact: &ArbResult) -> FrontComparison {
+    let missed_points = exact
+        .solutions
+        .keys()
+        .filter(|c| !heuristic.solutions.contains_key(*c))
+        .count();
+    let best_heuristic = heuristic.solutions.keys().map(social_cost).min();
+    let best_exact = exact.solutions.keys().map(social_cost).min();
+    let social_cost_gap = match (best_heuristic, best_exact) {
+        (Some(h), Some(e)) => h.saturating_sub(e),
+        _ => 0,
+    };
+    FrontComparison {
+        heuristic_points: heuristic.solutions.len(),
+        exact_points: exact.solutions.len(),
+        missed_points,
+        social_cost_gap,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    const F: Actions = Actions::Forward;
+
+    fn agent(x: i16, y: i16, orientation: Orientations, nsteps: usize) -> ArbAgent {
+        ArbAgent::new(
+            Coords::from(XYCell::new(x, y), orientation),
+            vec![F; nsteps],
+        )
+    }
+
+    #[test]
+    fn test_exact_contains_heuristic() {
+        let agents = vec![
+            agent(1, 0, Orientations::WEST, 3),
+            agent(-1, 1, Orientations::SOUTH, 3),
+            agent(-2, -1, Orientations::EAST, 3),
+            agent(0, -2, Orientations::NORTH, 3),
+        ];
+        let setup = ArbSetup { agents };
+        let heuristic = find_feasible_plans(&setup, 0);
+        let exact = find_exact_plans(&setup, default_max_delay(&setup));
+        // every heuristic solution is matched or beaten by the exact front
+        for c in heuristic.solutions.keys() {
+            assert!(exact.solutions.keys().any(|e| leq(e, c)));
+        }
+        let cmp = compare_fronts(&heuristic, &exact);
+        assert_eq!(cmp.exact_points, exact.solutions.len());
+        assert_eq!(cmp.heuristic_points, heuristic.solutions.len());
+        // the 4-way crossing has no interleaving better than the orders
+        assert_eq!(cmp.missed_points, 0);
+        assert_eq!(cmp.social_cost_gap, 0);
+    }
+
+    #[test]
+    fn test_exact_finds_interleaved_solution() {
+        // straight from the east and the north, left turn from the west
+        let agents = vec![
+            approach(Orientations::EAST, 0, Turn::Straight),
+            approach(Orientations::NORTH, 0, Turn::Straight),
+            approach(Orientations::WEST, 0, Turn::Left),
+        ];
+        let setup = ArbSetup { agents };
+        let heuristic = find_feasible_plans(&setup, 0);
+        let exact = find_exact_plans(&setup, default_max_delay(&setup));
+        // everybody waits a little: no order of the agents gives it
+        let interleaved = vec![2, 1, 1];
+        assert!(exact.solutions.contains_key(&interleaved));
+        assert!(!heuristic.solutions.keys().any(|c| leq(c, &interleaved)));
+        for c in heuristic.solutions.keys() {
+            assert!(exact.solutions.contains_key(c));
+        }
+        assert!(compare_fronts(&heuristic, &exact).missed_points > 0);
+    }
+
+    #[test]
+    fn test_exact_queue() {
+        let agents = (0..3)
+            .map(|i| agent(1 + i, 0, Orientations::WEST, 3))
+            .collect();
+        let setup = ArbSetup { agents };
+        let exact = find_exact_plans(&setup, default_max_delay(&setup));
+        assert_eq!(exact.solutions.len(), 1);
+    }
+}
@@ -0,0 +1,162 @@
+use std::fmt;
+
+use itertools::Itertools;
+
+use crate::{
+    get_resources_needed, mark_occupied, next_coords, plan_horizon, plan_resources, reserve_end,
+    simulate, Actions, ArbSetup, ArbSolution, RobotName, XYCell, RSM,
+};
+
+/// Why an agent had to wait at some time: another agent held a resource it needed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WaitExplanation {
+    /// The agent that waited
+    pub agent: usize,
+    /// The time of the wait
+    pub t: usize,
+    /// The agent holding the resource
+    pub blocker: usize,
+    /// The resource needed: the cell and the time at which it is held
+    pub resource_t: usize,
+    pub resource_xy: XYCell,
+}
+
+impl fmt::Display for WaitExplanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} waits at t={}: {} holds {:?} at t={}",
+            self.agent, self.t, self.blocker, self.resource_xy, self.resource_t
+        )
+    }
+}
+
+/// The resources reserved when `agent` was assigned: the starting cells of everybody
+/// and the plans of the agents before it in the order.
+fn resources_before(s: &ArbSetup, sol: &ArbSolution, agent: usize) -> RSM {
+    let horizon = plan_horizon(s);
+    let mut resources: RSM = Default::default();
+    for (a, other) in s.agents.iter().enumerate() {
+        mark_occupied(&mut resources, 0, &other.coord.xy, a);
+    }
+    for j in sol.perm.iter().take_while(|j| **j != agent) {
+        let other = &s.agents[*j];
+        let plan = &sol.robots[*j].plan;
+        for (rs, r) in plan_resources(&other.coord, plan, *j) {
+            resources.entry(rs).or_insert(r);
+        }
+        let last = simulate(other.coord, plan).last().copied().unwrap();
+        reserve_end(&mut resources, *j, other.end, plan.len(), &last.xy, horizon);
+    }
+    resources
+}
+
+/// Explains each wait that the arbitration added to the plans of the solution.
+pub fn explain(s: &ArbSetup, sol: &ArbSolution) -> Vec<WaitExplanation> {
+    let mut res = Vec::new();
+    for agent in 0..s.agents.len() {
+        let resources = resources_before(s, sol, agent);
+        let nominal = s.agents[agent].nominal_plan();
+        let mut coord = s.agents[agent].coord;
+        let mut k = 0;
+        for (t, action) in sol.robots[agent].plan.iter().enumerate() {
+            if k < nominal.len() && nominal[k] == *action {
+                k += 1;
+            } else if *action == Actions::Wait && k < nominal.len() {
+                let needed = get_resources_needed(t, &coord, nominal[k], agent);
+                // the earliest conflicting resource
+                let conflict = needed
+                    .keys()
+                    .filter(|rs| resources.get(rs).is_some_and(|other| *other != agent))
+                    .min_by_key(|rs| (rs.0, rs.1.x, rs.1.y));
+                if let Some(rs) = conflict {
+                    res.push(WaitExplanation {
+                        agent,
+                        t,
+                        blocker: resources[rs],
+                        resource_t: rs.0,
+                        resource_xy: rs.1,
+                    });
+                }
+            }
+            coord = next_coords(&coord, *action);
+        }
+    }
+    res
+}
+
+/// Follows the blockers starting from the wait of `agent` at time `t`: the agent was blocked
+/// by another one, which was itself waiting because of a third one, and so on.
+pub fn blocking_chain(
+    explanations: &[WaitExplanation],
+    agent: usize,
+    t: usize,
+) -> Vec<WaitExplanation> {
+    let mut chain: Vec<WaitExplanation> = Vec::new();
+    let mut current = explanations.iter().find(|e| e.agent == agent && e.t == t);
+    while let Some(e) = current {
+        if chain.iter().any(|c| c.agent == e.agent) {
+            break;
+        }
+        chain.push(*e);
+        // the blocker may itself have been waiting while holding the resource
+        current = explanations
+            .iter()
+            .find(|b| b.agent == e.blocker && (b.t == e.resource_t || b.t + 1 == e.resource_t));
+    }
+    chain
+}
+
+/// Describes a chain with the names of the robots.
+pub fn format_blocking_chain(chain: &[WaitExplanation], index2name: &[RobotName]) -> String {
+    chain
+        .iter()
+        .map(|e| {
+            format!(
+                "robot {} waits at t={} for robot {} holding {:?} at t={}",
+                index2name[e.agent], e.t, index2name[e.blocker], e.resource_xy, e.resource_t
+            )
+        })
+        .join("\n  because ")
+}
+
+#[cfg(test)]
+mod test {
+    use itertools::Itertools;
+
+    use crate::*;
+
+    const F: Actions = Actions::Forward;
+
+    #[test]
+    fn test_explain_queue() {
+        // three agents in a queue behind a crossing agent
+        let mut agents = (0..3)
+            .map(|i| {
+                ArbAgent::new(
+                    Coords::from(XYCell::new(1 + i, 0), Orientations::WEST),
+                    vec![F; 3],
+                )
+            })
+            .collect_vec();
+        agents.push(ArbAgent::new(
+            Coords::from(XYCell::new(0, -1), Orientations::NORTH),
+            vec![F; 3],
+        ));
+        let setup = ArbSetup { agents };
+        let (_, sol) = assign(&setup, &vec![3, 0, 1, 2]).unwrap();
+        let explanations = explain(&setup, &sol);
+        // every wait is explained
+        assert_eq!(explanations.len(), sol.costs.iter().sum::<usize>());
+        assert!(explanations.iter().all(|e| e.agent != 3));
+        let first = explanations.iter().find(|e| e.agent == 0).unwrap();
+        assert_eq!(first.blocker, 3);
+        // the last one of the queue waits because of the one in front, and so on
+        let chain = blocking_chain(&explanations, 2, 0);
+        let agents_in_chain = chain.iter().map(|e| e.agent).collect_vec();
+        assert_eq!(agents_in_chain, vec![2, 1, 0]);
+        assert_eq!(chain.last().unwrap().blocker, 3);
+        let text = format_blocking_chain(&chain, &[10, 11, 12, 13]);
+        assert!(text.starts_with("robot 12 waits at t=0 for robot 11"));
+    }
+}
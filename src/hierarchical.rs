@@ -0,0 +1,333 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use itertools::Itertools;
+
+use crate::{assign, find_feasible_plans, simulate, social_cost, ArbSetup, ArbSolution, XYCell};
+
+/// A region of the map: a block, as given by `Cell::block`, or a stretch of road outside the
+/// blocks, named by its smallest cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Region {
+    Block(XYCell),
+    Road(XYCell),
+}
+
+impl Region {
+    fn sort_key(&self) -> (usize, i16, i16) {
+        match self {
+            Region::Block(xy) => (0, xy.x, xy.y),
+            Region::Road(xy) => (1, xy.x, xy.y),
+        }
+    }
+}
+
+/// For each cell of the paths outside the blocks, the smallest cell of its stretch of road:
+/// the cells outside the blocks connected to it through the paths.
+fn road_stretches<F>(paths: &[Vec<XYCell>], block_of: F) -> HashMap<XYCell, XYCell>
+where
+    F: Fn(&XYCell) -> Option<XYCell>,
+{
+    let cells: HashSet<XYCell> = paths
+        .iter()
+        .flatten()
+        .filter(|xy| block_of(xy).is_none())
+        .copied()
+        .collect();
+    let mut res = HashMap::new();
+    for start in cells.iter().sorted_by_key(|xy| (xy.x, xy.y)) {
+        if res.contains_key(start) {
+            continue;
+        }
+        res.insert(*start, *start);
+        let mut todo = vec![*start];
+        while let Some(xy) = todo.pop() {
+            for d in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+                let next = xy + XYCell::new(d.0, d.1);
+                if cells.contains(&next) && !res.contains_key(&next) {
+                    res.insert(next, *start);
+                    todo.push(next);
+                }
+            }
+        }
+    }
+    res
+}
+
+/// For each region, the agents whose nominal path goes through it.
+/// `block_of` gives the block of a cell; the cells outside the blocks make one region per
+/// stretch of road, so that the roads between the blocks are not lumped into one game.
+/// The regions are sorted by coordinates, the blocks first.
+pub fn agents_by_region<F>(s: &ArbSetup, block_of: F) -> Vec<(Region, Vec<usize>)>
+where
+    F: Fn(&XYCell) -> Option<XYCell>,
+{
+    let paths = s
+        .agents
+        .iter()
+        .map(|a| {
+            simulate(a.coord, &a.plan)
+                .iter()
+                .map(|c| c.xy)
+                .collect_vec()
+        })
+        .collect_vec();
+    let roads = road_stretches(&paths, &block_of);
+    let mut res: HashMap<Region, Vec<usize>> = HashMap::new();
+    for (i, path) in paths.iter().enumerate() {
+        let regions = path
+            .iter()
+            .map(|xy| block_of(xy).map_or_else(|| Region::Road(roads[xy]), Region::Block))
+            .unique()
+            .collect_vec();
+        for r in regions {
+            res.entry(r).or_default().push(i);
+        }
+    }
+    res.into_iter()
+        .sorted_by_key(|(r, _)| r.sort_key())
+        .collect_vec()
+}
+
+fn has(set: &[u64], i: usize) -> bool {
+    set[i / 64] >> (i % 64) & 1 == 1
+}
+
+fn insert(set: &mut [u64], i: usize) {
+    set[i / 64] |= 1 << (i % 64);
+}
+
+fn members(set: &[u64]) -> impl Iterator<Item = usize> + '_ {
+    (0..set.len() * 64).filter(|i| has(set, *i))
+}
+
+/// Merges the orders decided in each region into one global order.
+///
+/// Each region order says that its agents go in that sequence. The constraints are added
+/// region by region; the ones contradicting what was already decided (they would make a
+/// cycle) are dropped, so that agents meeting in several regions keep the same relative order.
+/// Returns the global order and the number of constraints dropped.
+///
+/// The closure is kept as bit sets and a row is only updated when it gains the new successor,
+/// so that it takes O(n^3 / 64) besides the pairs of the orders.
+pub fn merge_region_orders(n: usize, orders: &[Vec<usize>]) -> (Vec<usize>, usize) {
+    // after[a] holds b and before[b] holds a when a must go before b (transitively closed)
+    let words = n.div_ceil(64);
+    let mut after = vec![vec![0u64; words]; n];
+    let mut before = vec![vec![0u64; words]; n];
+    let mut dropped = 0;
+    for order in orders {
+        for (k, &a) in order.iter().enumerate() {
+            for &b in &order[k + 1..] {
+                if has(&after[b], a) {
+                    dropped += 1;
+                    continue;
+                }
+                if has(&after[a], b) {
+                    continue;
+                }
+                // add a < b and everything it implies; the rows already holding b (or a)
+                // already hold what follows b (or precedes a)
+                let mut preds = before[a].clone();
+                insert(&mut preds, a);
+                let mut succs = after[b].clone();
+                insert(&mut succs, b);
+                for p in members(&preds) {
+                    if !has(&after[p], b) {
+                        after[p].iter_mut().zip(&succs).for_each(|(x, y)| *x |= y);
+                    }
+                }
+                for q in members(&succs) {
+                    if !has(&before[q], a) {
+                        before[q].iter_mut().zip(&preds).for_each(|(x, y)| *x |= y);
+                    }
+                }
+            }
+        }
+    }
+    // topological order, smallest index first among the available ones
+    let mut waiting = before
+        .iter()
+        .map(|set| set.iter().map(|w| w.count_ones() as usize).sum::<usize>())
+        .collect_vec();
+    let mut available = (0..n)
+        .filter(|b| waiting[*b] == 0)
+        .map(Reverse)
+        .collect::<BinaryHeap<_>>();
+    let mut res = Vec::with_capacity(n);
+    while let Some(Reverse(a)) = available.pop() {
+        res.push(a);
+        for b in members(&after[a]) {
+            waiting[b] -= 1;
+            if waiting[b] == 0 {
+                available.push(Reverse(b));
+            }
+        }
+    }
+    (res, dropped)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HierarchicalResult {
+    /// The global priority order
+    pub order: Vec<usize>,
+    /// The cell-level schedule for that order, if it is feasible
+    pub solution: Option<ArbSolution>,
+    /// Number of regions crossed by the agents
+    pub regions: usize,
+    /// Number of region-level constraints dropped to keep the orders consistent
+    pub dropped_constraints: usize,
+}
+
+/// Two-level arbitration: first the order of the agents in each region, solving the smaller
+/// game of the agents crossing it (with at most `max` orders, as in `find_feasible_plans`)
+/// and keeping its solution with the lowest total cost; then one global order consistent
+/// with the regions, which is scheduled cell by cell with `assign`.
+/// The regions crossed by the same agents are solved once.
+pub fn find_hierarchical_plan<F>(s: &ArbSetup, block_of: F, max: usize) -> HierarchicalResult
+where
+    F: Fn(&XYCell) -> Option<XYCell>,
+{
+    let regions = agents_by_region(s, block_of);
+    let mut orders = Vec::new();
+    let mut solved = HashSet::new();
+    for (_, members) in &regions {
+        if members.len() < 2 || !solved.insert(members) {
+            continue;
+        }
+        let sub = ArbSetup {
+            agents: members.iter().map(|i| s.agents[*i].clone()).collect_vec(),
+        };
+        let result = find_feasible_plans(&sub, max);
+        let best = result
+            .solutions
+            .values()
+            .flatten()
+            .min_by_key(|sol| (social_cost(&sol.costs), sol.perm.clone()));
+        if let Some(sol) = best {
+            orders.push(sol.perm.iter().map(|k| members[*k]).collect_vec());
+        }
+    }
+    let (order, dropped_constraints) = merge_region_orders(s.agents.len(), &orders);
+    let solution = assign(s, &order).map(|(_, sol)| sol);
+    HierarchicalResult {
+        order,
+        solution,
+        regions: regions.len(),
+        dropped_constraints,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use itertools::Itertools;
+
+    use crate::*;
+
+    #[test]
+    fn test_merge_region_orders() {
+        let (order, dropped) = merge_region_orders(4, &[vec![3, 1], vec![1, 2]]);
+        assert_eq!(order, vec![0, 3, 1, 2]);
+        assert_eq!(dropped, 0);
+        // the second region puts 2 and 0 before 3, contradicting 3 < 1 < 2 (< 0)
+        let (order, dropped) = merge_region_orders(4, &[vec![3, 1, 2], vec![2, 0, 3]]);
+        assert_eq!(dropped, 2);
+        let pos = |a| order.iter().position(|x| *x == a).unwrap();
+        assert!(pos(3) < pos(1) && pos(1) < pos(2) && pos(2) < pos(0));
+    }
+
+    #[test]
+    fn test_two_intersections() {
+        // the same crossing at two places far apart
+        let crossing = || {
+            four_way(&[
+                Approach::new(Orientations::EAST, 1, Turn::Straight),
+                Approach::new(Orientations::NORTH, 1, Turn::Straight),
+                Approach::new(Orientations::WEST, 1, Turn::Straight),
+                Approach::new(Orientations::SOUTH, 1, Turn::Straight),
+            ])
+            .setup
+        };
+        let shift = XYCell::new(20, 0);
+        let mut agents = crossing().agents;
+        agents.extend(crossing().agents.into_iter().map(|mut a| {
+            a.coord.xy = a.coord.xy + shift;
+            a
+        }));
+        let setup = ArbSetup { agents };
+        let region_of = |xy: &XYCell| Some(XYCell::new(if xy.x < 10 { 0 } else { 1 }, 0));
+        let regions = agents_by_region(&setup, region_of);
+        assert_eq!(
+            regions.iter().map(|(_, m)| m.len()).collect_vec(),
+            vec![4, 4]
+        );
+        let result = find_hierarchical_plan(&setup, region_of, 0);
+        assert_eq!(result.dropped_constraints, 0);
+        let solution = result.solution.unwrap();
+        // as good as the best of each crossing on its own
+        let best = min_social_cost(&find_feasible_plans(&crossing(), 0)).unwrap();
+        assert_eq!(social_cost(&solution.costs), 2 * best);
+    }
+
+    #[test]
+    fn test_blocks_and_connecting_road() {
+        // a crossing in each of two blocks and one on the road between them,
+        // and an agent driving from the first block to the second one along the road
+        let approaches = [
+            Orientations::EAST,
+            Orientations::NORTH,
+            Orientations::WEST,
+            Orientations::SOUTH,
+        ]
+        .map(|side| Approach::new(side, 1, Turn::Straight));
+        let crossing = |dx: i16| {
+            four_way(&approaches)
+                .setup
+                .agents
+                .into_iter()
+                .map(move |mut a| {
+                    a.coord.xy = a.coord.xy + XYCell::new(dx, 0);
+                    a
+                })
+        };
+        let mut agents = crossing(0)
+            .chain(crossing(7))
+            .chain(crossing(20))
+            .collect_vec();
+        agents.push(ArbAgent::new(
+            Coords::from(XYCell::new(0, 3), Orientations::EAST),
+            vec![Actions::Forward; 21],
+        ));
+        let through = agents.len() - 1;
+        let setup = ArbSetup { agents };
+        let block_of = |xy: &XYCell| match xy.x {
+            x if x < 5 => Some(XYCell::new(0, 0)),
+            x if x >= 10 => Some(XYCell::new(1, 0)),
+            _ => None,
+        };
+        let regions = agents_by_region(&setup, block_of);
+        assert_eq!(
+            regions[0],
+            (Region::Block(XYCell::new(0, 0)), vec![0, 1, 2, 3, through])
+        );
+        assert_eq!(
+            regions[1],
+            (
+                Region::Block(XYCell::new(1, 0)),
+                vec![8, 9, 10, 11, through]
+            )
+        );
+        // the crossing on the road and the road taken by the last agent are regions of their own
+        assert_eq!(regions.len(), 4);
+        assert_eq!(
+            regions[2],
+            (Region::Road(XYCell::new(5, -1)), vec![4, 5, 6, 7])
+        );
+        assert_eq!(regions[3], (Region::Road(XYCell::new(5, 3)), vec![through]));
+        let result = find_hierarchical_plan(&setup, block_of, 0);
+        assert_eq!(result.dropped_constraints, 0);
+        let solution = result.solution.unwrap();
+        let best = min_social_cost(&find_feasible_plans(&four_way(&approaches).setup, 0)).unwrap();
+        assert_eq!(social_cost(&solution.costs), 3 * best);
+    }
+}
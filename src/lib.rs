@@ -11,6 +11,50 @@ mod efficient_setsampling;
 pub use efficient_setsampling::*;
 mod arbitration;
 pub use arbitration::*;
+mod vcg;
+pub use vcg::*;
+mod nash;
+pub use nash::*;
+mod exact;
+pub use exact::*;
+mod commitment;
+pub use commitment::*;
+mod anytime;
+pub use anytime::*;
+mod symmetry;
+pub use symmetry::*;
+mod costs;
+pub use costs::*;
+mod stackelberg;
+pub use stackelberg::*;
+mod explain;
+pub use explain::*;
+mod diagram;
+pub use diagram::*;
+mod ascii;
+pub use ascii::*;
+mod ascii_map;
+pub use ascii_map::*;
+mod png_map;
+pub use png_map::*;
+mod movingai;
+pub use movingai::*;
+mod osm;
+pub use osm::*;
+mod scenarios;
+pub use scenarios::*;
+mod hierarchical;
+pub use hierarchical::*;
+mod checkpoint;
+pub use checkpoint::*;
+mod signals;
+pub use signals::*;
+mod battery;
+pub use battery::*;
+mod vehicles;
+pub use vehicles::*;
+mod closures;
+pub use closures::*;
 
 // type AgentName = String;
 // type AgentState = f32;
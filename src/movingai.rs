@@ -0,0 +1,255 @@
+use itertools::Itertools;
+
+use crate::{orientations_from_mask, Cell, Coords, Grid, Orientations, XYCell};
+
+/// A start/goal pair of a MovingAI `.scen` file
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapfTask {
+    pub bucket: usize,
+    /// The map file, as written in the scenario
+    pub map: String,
+    pub start: XYCell,
+    pub goal: XYCell,
+    pub optimal_length: f64,
+}
+
+impl MapfTask {
+    /// The start with the first orientation allowed there
+    pub fn start_coords(&self, grid: &Grid) -> Option<Coords> {
+        first_allowed(grid, self.start)
+    }
+
+    pub fn goal_coords(&self, grid: &Grid) -> Option<Coords> {
+        first_allowed(grid, self.goal)
+    }
+}
+
+fn first_allowed(grid: &Grid, xy: XYCell) -> Option<Coords> {
+    if !grid.size.in_bounds(xy) {
+        return None;
+    }
+    let mask = grid.get_cell(&xy).orientation_mask();
+    orientations_from_mask(mask)
+        .first()
+        .map(|o| Coords::from(xy, *o))
+}
+
+/// MovingAI rows go down from the top, ours go up from the bottom.
+fn flip_y(xy: XYCell, height: i16) -> XYCell {
+    XYCell::new(xy.x, height - 1 - xy.y)
+}
+
+/// Parses a MovingAI `.map` file. `.`, `G` and `S` are traversable in all orientations;
+/// `T` and `W` are obstacles and `@` and `O` are out of the map.
+pub fn parse_movingai_map(text: &str) -> Result<Grid, String> {
+    let mut lines = text.lines();
+    let mut height = None;
+    let mut width = None;
+    for line in lines.by_ref() {
+        let line = line.trim();
+        if line == "map" {
+            break;
+        }
+        match line.split_whitespace().collect_vec().as_slice() {
+            ["type", _] => {}
+            ["height", h] => height = h.parse::<i16>().ok(),
+            ["width", w] => width = w.parse::<i16>().ok(),
+            _ => return Err(format!("invalid header line {line:?}")),
+        }
+    }
+    let (Some(height), Some(width)) = (height, width) else {
+        return Err("missing height or width".to_string());
+    };
+    let rows = lines
+        .map(|line| line.trim_end().chars().collect_vec())
+        .filter(|row| !row.is_empty())
+        .collect_vec();
+    if rows.len() != height as usize {
+        return Err(format!("{} rows instead of {height}", rows.len()));
+    }
+    let mut grid = Grid::new(XYCell::new(width, height));
+    for (r, row) in rows.iter().enumerate() {
+        if row.len() != width as usize {
+            return Err(format!(
+                "row {r} has {} cells instead of {width}",
+                row.len()
+            ));
+        }
+        for (x, c) in row.iter().enumerate() {
+            let xy = flip_y(XYCell::new(x as i16, r as i16), height);
+            match c {
+                '.' | 'G' | 'S' => {
+                    for o in [
+                        Orientations::NORTH,
+                        Orientations::SOUTH,
+                        Orientations::WEST,
+                        Orientations::EAST,
+                    ] {
+                        grid.draw_road(&Coords::from(xy, o));
+                    }
+                }
+                'T' | 'W' => grid.replace_cell(&xy, Cell::obstacle()),
+                '@' | 'O' => {}
+                _ => return Err(format!("invalid glyph {c:?} at row {r}")),
+            }
+        }
+    }
+    Ok(grid)
+}
+
+/// Writes the grid as an `octile` MovingAI map: the traversable cells become `.`,
+/// whatever their orientations, the obstacles `T` and the rest `@`.
+pub fn format_movingai_map(grid: &Grid) -> String {
+    let mut lines = vec![
+        "type octile".to_string(),
+        format!("height {}", grid.size.y),
+        format!("width {}", grid.size.x),
+        "map".to_string(),
+    ];
+    for y in (0..grid.size.y).rev() {
+        let line: String = (0..grid.size.x)
+            .map(|x| {
+                let cell = grid.get_cell(&XYCell::new(x, y));
+                if cell.is_obstacle {
+                    'T'
+                } else if cell.traversable() {
+                    '.'
+                } else {
+                    '@'
+                }
+            })
+            .collect();
+        lines.push(line);
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Parses a MovingAI `.scen` file (version 1), converting the coordinates to ours.
+pub fn parse_movingai_scen(text: &str) -> Result<Vec<MapfTask>, String> {
+    let mut tasks = Vec::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("version") {
+            continue;
+        }
+        let fields = line.split('\t').collect_vec();
+        let [bucket, map, _width, height, sx, sy, gx, gy, optimal_length] = fields.as_slice()
+        else {
+            return Err(format!("expected 9 fields in {line:?}"));
+        };
+        let int = |s: &str| {
+            s.parse::<i16>()
+                .map_err(|_| format!("invalid number {s:?} in {line:?}"))
+        };
+        let height = int(height)?;
+        tasks.push(MapfTask {
+            bucket: bucket
+                .parse()
+                .map_err(|_| format!("invalid bucket {bucket:?} in {line:?}"))?,
+            map: map.to_string(),
+            start: flip_y(XYCell::new(int(sx)?, int(sy)?), height),
+            goal: flip_y(XYCell::new(int(gx)?, int(gy)?), height),
+            optimal_length: optimal_length
+                .parse()
+                .map_err(|_| format!("invalid length {optimal_length:?} in {line:?}"))?,
+        });
+    }
+    Ok(tasks)
+}
+
+/// Writes the tasks as a `.scen` file for a map of the given size.
+pub fn format_movingai_scen(size: XYCell, tasks: &[MapfTask]) -> String {
+    let mut lines = vec!["version 1".to_string()];
+    for task in tasks {
+        let start = flip_y(task.start, size.y);
+        let goal = flip_y(task.goal, size.y);
+        lines.push(
+            [
+                task.bucket.to_string(),
+                task.map.clone(),
+                size.x.to_string(),
+                size.y.to_string(),
+                start.x.to_string(),
+                start.y.to_string(),
+                goal.x.to_string(),
+                goal.y.to_string(),
+                task.optimal_length.to_string(),
+            ]
+            .join("\t"),
+        );
+    }
+    lines.join("\n") + "\n"
+}
+
+fn read(filename: &str) -> Result<String, String> {
+    std::fs::read_to_string(filename).map_err(|e| format!("cannot read {filename}: {e}"))
+}
+
+fn write(filename: &str, text: &str) -> Result<(), String> {
+    std::fs::write(filename, text).map_err(|e| format!("cannot save {filename}: {e}"))
+}
+
+pub fn load_movingai_map(filename: &str) -> Result<Grid, String> {
+    parse_movingai_map(&read(filename)?)
+}
+
+pub fn save_movingai_map(grid: &Grid, filename: &str) -> Result<(), String> {
+    write(filename, &format_movingai_map(grid))
+}
+
+pub fn load_movingai_scen(filename: &str) -> Result<Vec<MapfTask>, String> {
+    parse_movingai_scen(&read(filename)?)
+}
+
+pub fn save_movingai_scen(size: XYCell, tasks: &[MapfTask], filename: &str) -> Result<(), String> {
+    write(filename, &format_movingai_scen(size, tasks))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    const MAP: &str = "type octile
+height 3
+width 5
+map
+..T@.
+.....
+@@..G
+";
+
+    const SCEN: &str = "version 1
+0\tsmall.map\t5\t3\t0\t0\t4\t2\t6
+1\tsmall.map\t5\t3\t1\t1\t3\t2\t3.41421356
+";
+
+    #[test]
+    fn test_movingai_map() {
+        let grid = parse_movingai_map(MAP).unwrap();
+        assert_eq!(grid.size, XYCell::new(5, 3));
+        // the first row is the top one
+        assert!(grid.get_cell(&XYCell::new(2, 2)).is_obstacle);
+        assert!(!grid.get_cell(&XYCell::new(0, 0)).traversable());
+        assert_eq!(grid.get_cell(&XYCell::new(4, 0)).orientation_mask(), 0b1111);
+        let text = format_movingai_map(&grid);
+        assert_eq!(text, MAP.replace('G', "."));
+        assert!(parse_movingai_map("height 1\nwidth 2\nmap\n...\n").is_err());
+        assert!(parse_movingai_map("height 2\nwidth 2\nmap\n..\n").is_err());
+    }
+
+    #[test]
+    fn test_movingai_scen() {
+        let grid = parse_movingai_map(MAP).unwrap();
+        let tasks = parse_movingai_scen(SCEN).unwrap();
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks[0].start, XYCell::new(0, 2));
+        assert_eq!(tasks[0].goal, XYCell::new(4, 0));
+        assert_eq!(tasks[1].bucket, 1);
+        assert_eq!(
+            tasks[0].start_coords(&grid),
+            Some(Coords::from(XYCell::new(0, 2), Orientations::NORTH))
+        );
+        assert_eq!(format_movingai_scen(grid.size, &tasks), SCEN);
+        assert!(parse_movingai_scen("0\tsmall.map\t5\t3\t0\t0\t4\t2").is_err());
+    }
+}
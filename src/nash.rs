@@ -0,0 +1,205 @@
+use itertools::Itertools;
+
+use crate::{
+    are_resources_available, assign, get_resources_needed, mark_occupied, next_coords,
+    plan_horizon, reserve_end, simulate, Actions, ArbResult, ArbSetup, ArbSolution, Coords, Cost,
+    Plan, RobotName, RSM,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DeviationKind {
+    /// The agent keeps the others' schedules fixed and waits at different times
+    Retime,
+    /// The agent moves itself to this (earlier) position in the priority order
+    ClaimSlot { position: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Deviation {
+    pub agent: usize,
+    pub kind: DeviationKind,
+    /// The plan the agent would follow after deviating
+    pub plan: Plan,
+    /// The cost the agent would get after deviating
+    pub cost: Cost,
+}
+
+/// The resources used by an agent following the given plan.
+pub fn plan_resources(coord: &Coords, plan: &[Actions], robot_name: RobotName) -> RSM {
+    let mut resources: RSM = Default::default();
+    mark_occupied(&mut resources, 0, &coord.xy, robot_name);
+    let mut coord = *coord;
+    for (t, action) in plan.iter().enumerate() {
+        for (rs, r) in get_resources_needed(t, &coord, *action, robot_name) {
+            mark_occupied(&mut resources, rs.0, &rs.1, r);
+        }
+        coord = next_coords(&coord, *action);
+    }
+    resources
+}
+
+/// Whether the agent can follow the plan without conflicting with the resources.
+pub fn is_plan_feasible(
+    resources_committed: &RSM,
+    robot_name: RobotName,
+    coord: &Coords,
+    plan: &[Actions],
+) -> bool {
+    let mut coord = *coord;
+    for (t, action) in plan.iter().enumerate() {
+        let needed = get_resources_needed(t, &coord, *action, robot_name);
+        if !are_resources_available(resources_committed, &needed) {
+            return false;
+        }
+        coord = next_coords(&coord, *action);
+    }
+    true
+}
+
+/// All the ways of inserting `nwaits` waits before the actions of the plan.
+pub fn wait_insertions(plan: &[Actions], nwaits: usize) -> Vec<Plan> {
+    if plan.is_empty() {
+        return vec![vec![Actions::Wait; nwaits]];
+    }
+    let mut res = Vec::new();
+    for before in 0..=nwaits {
+        let mut head = vec![Actions::Wait; before];
+        head.push(plan[0]);
+        for tail in wait_insertions(&plan[1..], nwaits - before) {
+            let mut p = head.clone();
+            p.extend(tail);
+            res.push(p);
+        }
+    }
+    res
+}
+
+fn others_resources(s: &ArbSetup, solution: &ArbSolution, agent: usize) -> RSM {
+    let horizon = plan_horizon(s);
+    let mut resources: RSM = Default::default();
+    for (j, other) in s.agents.iter().enumerate() {
+        if j == agent {
+            continue;
+        }
+        let plan = &solution.robots[j].plan;
+        for (rs, r) in plan_resources(&other.coord, plan, j) {
+            mark_occupied(&mut resources, rs.0, &rs.1, r);
+        }
+        let last = simulate(other.coord, plan).last().copied().unwrap();
+        reserve_end(&mut resources, j, other.end, plan.len(), &last.xy, horizon);
+    }
+    resources
+}
+
+fn retime_deviations(s: &ArbSetup, solution: &ArbSolution, agent: usize) -> Vec<Deviation> {
+    let resources = others_resources(s, solution, agent);
+    let horizon = plan_horizon(s);
+    let a = &s.agents[agent];
+    let mut res = Vec::new();
+    for nwaits in 0..solution.costs[agent] {
+        for plan in wait_insertions(a.nominal_plan(), nwaits)
+            .into_iter()
+            .unique()
+        {
+            let last = simulate(a.coord, &plan).last().copied().unwrap();
+            let mut with_end = resources.clone();
+            if is_plan_feasible(&resources, agent, &a.coord, &plan)
+                && reserve_end(&mut with_end, agent, a.end, plan.len(), &last.xy, horizon)
+            {
+                res.push(Deviation {
+                    agent,
+                    kind: DeviationKind::Retime,
+                    plan,
+                    cost: nwaits,
+                });
+            }
+        }
+    }
+    res
+}
+
+fn claim_slot_deviations(s: &ArbSetup, solution: &ArbSolution, agent: usize) -> Vec<Deviation> {
+    let current = solution.perm.iter().position(|x| *x == agent).unwrap();
+    let mut res = Vec::new();
+    for position in 0..current {
+        let mut perm = solution.perm.clone();
+        perm.remove(current);
+        perm.insert(position, agent);
+        if let Some((_, alternative)) = assign(s, &perm) {
+            if alternative.costs[agent] < solution.costs[agent] {
+                res.push(Deviation {
+                    agent,
+                    kind: DeviationKind::ClaimSlot { position },
+                    plan: alternative.robots[agent].plan.clone(),
+                    cost: alternative.costs[agent],
+                });
+            }
+        }
+    }
+    res
+}
+
+/// Lists the unilateral deviations that would lower some agent's own cost.
+pub fn profitable_deviations(s: &ArbSetup, solution: &ArbSolution) -> Vec<Deviation> {
+    let mut res = Vec::new();
+    for agent in 0..s.agents.len() {
+        res.extend(retime_deviations(s, solution, agent));
+        res.extend(claim_slot_deviations(s, solution, agent));
+    }
+    res
+}
+
+pub fn is_nash_stable(s: &ArbSetup, solution: &ArbSolution) -> bool {
+    profitable_deviations(s, solution).is_empty()
+}
+
+/// The solutions of the result that no agent wants to deviate from.
+pub fn equilibria<'a>(s: &ArbSetup, result: &'a ArbResult) -> Vec<&'a ArbSolution> {
+    result
+        .solutions
+        .values()
+        .flatten()
+        .filter(|sol| is_nash_stable(s, sol))
+        .collect_vec()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    const F: Actions = Actions::Forward;
+
+    #[test]
+    fn test_wait_insertions() {
+        let plans = wait_insertions(&[F, F], 1);
+        assert_eq!(plans.len(), 3);
+        assert!(plans.iter().all(|p| p.len() == 3));
+    }
+
+    #[test]
+    fn test_nash_crossing() {
+        // two agents crossing the same cell
+        let e = ArbAgent::new(
+            Coords::from(XYCell::new(1, 0), Orientations::WEST),
+            vec![F; 3],
+        );
+        let s = ArbAgent::new(
+            Coords::from(XYCell::new(0, -1), Orientations::NORTH),
+            vec![F; 3],
+        );
+        let setup = ArbSetup { agents: vec![e, s] };
+        let result = find_feasible_plans(&setup, 0);
+        for sol in result.solutions.values().flatten() {
+            let deviations = profitable_deviations(&setup, sol);
+            // the agent that yields can always claim the first slot instead
+            let waiting = sol.costs.iter().position(|c| *c > 0).unwrap();
+            assert!(deviations
+                .iter()
+                .any(|d| d.agent == waiting && d.kind == DeviationKind::ClaimSlot { position: 0 }));
+            // but it cannot simply stop waiting while the other keeps its schedule
+            assert!(!deviations
+                .iter()
+                .any(|d| d.agent == waiting && d.kind == DeviationKind::Retime));
+        }
+    }
+}
@@ -0,0 +1,400 @@
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use xml::reader::{EventReader, XmlEvent};
+
+use crate::{Coords, Grid, Orientations, XYCell};
+
+/// Length of a degree of latitude, on a sphere of radius 6371 km
+pub const METERS_PER_DEGREE: f64 = 111_194.93;
+
+/// Highway values used by vehicles; the others (footways, paths, ...) are skipped
+pub const DRIVABLE_HIGHWAYS: [&str; 17] = [
+    "motorway",
+    "motorway_link",
+    "trunk",
+    "trunk_link",
+    "primary",
+    "primary_link",
+    "secondary",
+    "secondary_link",
+    "tertiary",
+    "tertiary_link",
+    "unclassified",
+    "residential",
+    "living_street",
+    "service",
+    "road",
+    "busway",
+    "escape",
+];
+
+pub type Tags = HashMap<String, String>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OsmNode {
+    pub lat: f64,
+    pub lon: f64,
+    pub tags: Tags,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OsmWay {
+    pub nodes: Vec<i64>,
+    pub tags: Tags,
+}
+
+/// The nodes and ways of an OpenStreetMap XML file; relations are ignored.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OsmData {
+    pub nodes: HashMap<i64, OsmNode>,
+    pub ways: Vec<OsmWay>,
+}
+
+fn attribute(attributes: &[xml::attribute::OwnedAttribute], name: &str) -> Result<String, String> {
+    attributes
+        .iter()
+        .find(|a| a.name.local_name == name)
+        .map(|a| a.value.clone())
+        .ok_or(format!("missing attribute {name:?}"))
+}
+
+fn number<T: std::str::FromStr>(value: String) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid number {value:?}"))
+}
+
+pub fn parse_osm(text: &str) -> Result<OsmData, String> {
+    let mut data = OsmData::default();
+    let mut node: Option<(i64, OsmNode)> = None;
+    let mut way: Option<OsmWay> = None;
+    for event in EventReader::from_str(text) {
+        match event.map_err(|e| format!("invalid XML: {e}"))? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => match name.local_name.as_str() {
+                "node" => {
+                    let id = number(attribute(&attributes, "id")?)?;
+                    let lat = number(attribute(&attributes, "lat")?)?;
+                    let lon = number(attribute(&attributes, "lon")?)?;
+                    let tags = Tags::new();
+                    node = Some((id, OsmNode { lat, lon, tags }));
+                }
+                "way" => {
+                    way = Some(OsmWay {
+                        nodes: Vec::new(),
+                        tags: Tags::new(),
+                    })
+                }
+                "nd" => {
+                    if let Some(way) = way.as_mut() {
+                        way.nodes.push(number(attribute(&attributes, "ref")?)?);
+                    }
+                }
+                "tag" => {
+                    let k = attribute(&attributes, "k")?;
+                    let v = attribute(&attributes, "v")?;
+                    if let Some((_, node)) = node.as_mut() {
+                        node.tags.insert(k, v);
+                    } else if let Some(way) = way.as_mut() {
+                        way.tags.insert(k, v);
+                    }
+                }
+                _ => {}
+            },
+            XmlEvent::EndElement { name } => match name.local_name.as_str() {
+                "node" => {
+                    if let Some((id, node)) = node.take() {
+                        data.nodes.insert(id, node);
+                    }
+                }
+                "way" => data.ways.extend(way.take()),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    Ok(data)
+}
+
+fn is_drivable(way: &OsmWay) -> bool {
+    way.tags
+        .get("highway")
+        .is_some_and(|h| DRIVABLE_HIGHWAYS.contains(&h.as_str()))
+}
+
+/// 1 if the way is one-way along its nodes, -1 if against them, 0 if two-way.
+fn oneway(way: &OsmWay) -> i8 {
+    let implied = way.tags.get("highway").is_some_and(|h| h == "motorway")
+        || way.tags.get("junction").is_some_and(|j| j == "roundabout");
+    match way.tags.get("oneway").map(|s| s.as_str()) {
+        Some("yes") | Some("true") | Some("1") => 1,
+        Some("-1") | Some("reverse") => -1,
+        Some("no") | Some("false") | Some("0") => 0,
+        _ if implied => 1,
+        _ => 0,
+    }
+}
+
+fn is_parking(tags: &Tags) -> bool {
+    tags.get("amenity").is_some_and(|a| a == "parking")
+}
+
+/// Equirectangular projection in meters around `origin` (lat, lon).
+fn project(origin: (f64, f64), lat: f64, lon: f64) -> (f64, f64) {
+    let x = (lon - origin.1) * METERS_PER_DEGREE * origin.0.to_radians().cos();
+    let y = (lat - origin.0) * METERS_PER_DEGREE;
+    (x, y)
+}
+
+fn cell_of(p: (f64, f64), cell_size_m: f64) -> XYCell {
+    XYCell::new(
+        (p.0 / cell_size_m).floor() as i16,
+        (p.1 / cell_size_m).floor() as i16,
+    )
+}
+
+/// The 4-connected cells crossed by the segment, in order.
+fn cells_along(p: (f64, f64), q: (f64, f64), cell_size_m: f64) -> Vec<XYCell> {
+    let length = (q.0 - p.0).hypot(q.1 - p.1) / cell_size_m;
+    // small enough steps that no cell is skipped
+    let n = (length * 4.0).ceil().max(1.0) as usize;
+    let mut cells: Vec<XYCell> = Vec::new();
+    for i in 0..=n {
+        let t = i as f64 / n as f64;
+        let xy = cell_of((p.0 + t * (q.0 - p.0), p.1 + t * (q.1 - p.1)), cell_size_m);
+        if let Some(last) = cells.last().copied() {
+            if last == xy {
+                continue;
+            }
+            if last.x != xy.x && last.y != xy.y {
+                cells.push(XYCell::new(xy.x, last.y));
+            }
+        }
+        cells.push(xy);
+    }
+    cells
+}
+
+fn direction(from: XYCell, to: XYCell) -> Orientations {
+    match (to.x - from.x, to.y - from.y) {
+        (1, 0) => Orientations::EAST,
+        (-1, 0) => Orientations::WEST,
+        (0, 1) => Orientations::NORTH,
+        _ => Orientations::SOUTH,
+    }
+}
+
+/// Places a parking spot near `xy`: the closest cell off the road that a road leads to,
+/// facing away from it. The road cell behind must already allow that orientation, so
+/// that the spot does not open a street in a new direction.
+/// Returns false if there is none within `radius` cells.
+fn place_parking(grid: &mut Grid, xy: XYCell, radius: i16) -> bool {
+    let mut candidates = Vec::new();
+    for dx in -radius..=radius {
+        for dy in -radius..=radius {
+            let p = XYCell::new(xy.x + dx, xy.y + dy);
+            if !grid.size.in_bounds(p) || grid.get_cell(&p).traversable() {
+                continue;
+            }
+            for o in [
+                Orientations::NORTH,
+                Orientations::SOUTH,
+                Orientations::WEST,
+                Orientations::EAST,
+            ] {
+                let behind = p - o.vector();
+                let leads = grid.size.in_bounds(behind) && {
+                    let cell = grid.get_cell(&behind);
+                    !cell.is_parking && !cell.is_charging && cell.is_allowed(o)
+                };
+                if leads {
+                    candidates.push((dx.abs() + dy.abs(), p.x, p.y, o as usize));
+                }
+            }
+        }
+    }
+    match candidates.into_iter().min() {
+        Some((_, x, y, o)) => {
+            let o = [
+                Orientations::NORTH,
+                Orientations::SOUTH,
+                Orientations::WEST,
+                Orientations::EAST,
+            ][o];
+            grid.make_parking_cell(&Coords::from(XYCell::new(x, y), o));
+            true
+        }
+        None => false,
+    }
+}
+
+/// Rasterizes the drivable highways onto a grid with cells of `cell_size_m` meters:
+/// the cells crossed by a way allow the orientations of its direction of travel,
+/// both ways unless it is one-way.
+/// Parking amenities (nodes, or ways by their centroid) become parking spots next to the
+/// closest road, in the order of their ids; those that no road leads to within 4 cells
+/// are skipped.
+pub fn rasterize_osm(data: &OsmData, cell_size_m: f64) -> Result<Grid, String> {
+    let roads = data.ways.iter().filter(|w| is_drivable(w)).collect_vec();
+    let node = |id: &i64| {
+        data.nodes
+            .get(id)
+            .ok_or(format!("way refers to the missing node {id}"))
+    };
+    // the spots taken by a parking are not free for the next ones: place them in a fixed order
+    let mut parkings = data
+        .nodes
+        .iter()
+        .filter(|(_, n)| is_parking(&n.tags))
+        .sorted_by_key(|(id, _)| **id)
+        .map(|(_, n)| (n.lat, n.lon))
+        .collect_vec();
+    for way in data.ways.iter().filter(|w| is_parking(&w.tags)) {
+        let nodes = way.nodes.iter().map(node).collect::<Result<Vec<_>, _>>()?;
+        if !nodes.is_empty() {
+            let n = nodes.len() as f64;
+            let lat = nodes.iter().map(|n| n.lat).sum::<f64>() / n;
+            let lon = nodes.iter().map(|n| n.lon).sum::<f64>() / n;
+            parkings.push((lat, lon));
+        }
+    }
+    let mut used = Vec::new();
+    for way in roads.iter() {
+        for id in way.nodes.iter() {
+            let n = node(id)?;
+            used.push((n.lat, n.lon));
+        }
+    }
+    if used.is_empty() {
+        return Err("no drivable highway".to_string());
+    }
+    used.extend(parkings.iter().copied());
+    let min_lat = used.iter().map(|p| p.0).fold(f64::INFINITY, f64::min);
+    let min_lon = used.iter().map(|p| p.1).fold(f64::INFINITY, f64::min);
+    let origin = (min_lat, min_lon);
+    let cells = used
+        .iter()
+        .map(|(lat, lon)| cell_of(project(origin, *lat, *lon), cell_size_m))
+        .collect_vec();
+    let width = cells.iter().map(|c| c.x as i32).max().unwrap() + 1;
+    let height = cells.iter().map(|c| c.y as i32).max().unwrap() + 1;
+    if width > i16::MAX as i32 || height > i16::MAX as i32 {
+        return Err(format!("{width}x{height} cells is too large a grid"));
+    }
+    let mut grid = Grid::new(XYCell::new(width as i16, height as i16));
+    for way in roads {
+        let points = way
+            .nodes
+            .iter()
+            .map(|id| node(id).map(|n| project(origin, n.lat, n.lon)))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut path: Vec<XYCell> = Vec::new();
+        for (p, q) in points.iter().tuple_windows() {
+            for xy in cells_along(*p, *q, cell_size_m) {
+                if path.last() != Some(&xy) {
+                    path.push(xy);
+                }
+            }
+        }
+        let oneway = oneway(way);
+        for (a, b) in path.iter().tuple_windows() {
+            let forward = direction(*a, *b);
+            let backward = forward.rotate_left().rotate_left();
+            for xy in [a, b] {
+                if oneway >= 0 {
+                    grid.draw_road(&Coords::from(*xy, forward));
+                }
+                if oneway <= 0 {
+                    grid.draw_road(&Coords::from(*xy, backward));
+                }
+            }
+        }
+    }
+    for (lat, lon) in parkings {
+        let xy = cell_of(project(origin, lat, lon), cell_size_m);
+        place_parking(&mut grid, xy, 4);
+    }
+    Ok(grid)
+}
+
+pub fn load_osm_grid(filename: &str, cell_size_m: f64) -> Result<Grid, String> {
+    let text =
+        std::fs::read_to_string(filename).map_err(|e| format!("cannot read {filename}: {e}"))?;
+    rasterize_osm(&parse_osm(&text)?, cell_size_m)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    const LAT: f64 = 45.0;
+    const LON: f64 = 7.0;
+
+    /// A node `x` meters east and `y` meters north of the origin
+    fn node(id: i64, x: f64, y: f64, tags: &str) -> String {
+        let lat = LAT + y / METERS_PER_DEGREE;
+        let lon = LON + x / (METERS_PER_DEGREE * LAT.to_radians().cos());
+        format!("<node id=\"{id}\" lat=\"{lat}\" lon=\"{lon}\">{tags}</node>")
+    }
+
+    fn way(nodes: &[i64], tags: &str) -> String {
+        let nds: String = nodes.iter().map(|n| format!("<nd ref=\"{n}\"/>")).collect();
+        format!("<way id=\"1\">{nds}{tags}</way>")
+    }
+
+    fn sample() -> String {
+        [
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?><osm version=\"0.6\">".to_string(),
+            node(1, 0.0, 0.0, ""),
+            node(2, 52.5, 0.0, ""),
+            node(3, 27.5, 0.0, ""),
+            node(4, 27.5, 22.5, ""),
+            node(5, 52.5, 32.5, ""),
+            node(6, 32.5, 27.5, "<tag k=\"amenity\" v=\"parking\"/>"),
+            node(7, 32.5, 12.5, "<tag k=\"amenity\" v=\"parking\"/>"),
+            way(&[1, 2], "<tag k=\"highway\" v=\"residential\"/>"),
+            way(
+                &[3, 4],
+                "<tag k=\"highway\" v=\"service\"/><tag k=\"oneway\" v=\"yes\"/>",
+            ),
+            way(&[2, 5], "<tag k=\"highway\" v=\"footway\"/>"),
+            "</osm>".to_string(),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_parse_osm() {
+        let data = parse_osm(&sample()).unwrap();
+        assert_eq!(data.nodes.len(), 7);
+        assert_eq!(data.ways.len(), 3);
+        assert_eq!(data.ways[1].nodes, vec![3, 4]);
+        assert_eq!(data.ways[1].tags["oneway"], "yes");
+        assert!(parse_osm("<osm><node id=\"1\"/></osm>").is_err());
+    }
+
+    #[test]
+    fn test_rasterize_osm() {
+        let grid = rasterize_osm(&parse_osm(&sample()).unwrap(), 5.0).unwrap();
+        assert_eq!(grid.size, XYCell::new(11, 6));
+        // the two-way street
+        assert_eq!(grid.get_cell(&XYCell::new(2, 0)).orientation_mask(), 0b1100);
+        // the one-way street going north
+        assert_eq!(grid.get_cell(&XYCell::new(5, 4)).orientation_mask(), 0b0001);
+        assert_eq!(grid.get_cell(&XYCell::new(5, 0)).orientation_mask(), 0b1101);
+        // the footway is skipped
+        assert!(!grid.get_cell(&XYCell::new(10, 3)).traversable());
+        // the parking spot is entered from the end of the one-way street
+        let parking = grid.get_cell(&XYCell::new(5, 5));
+        assert!(parking.is_parking);
+        assert!(parking.is_allowed(Orientations::NORTH));
+        // the one beside it would need a turn the street does not allow: it is skipped
+        // rather than opening the street eastward
+        assert!(!grid.get_cell(&XYCell::new(6, 2)).traversable());
+        for y in 1..5 {
+            assert_eq!(grid.get_cell(&XYCell::new(5, y)).orientation_mask(), 0b0001);
+        }
+    }
+}
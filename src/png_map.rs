@@ -0,0 +1,245 @@
+use image::{Rgb, RgbImage};
+use itertools::Itertools;
+
+use crate::{
+    next_coords, orientations_from_mask, Actions, Cell, Coords, Grid, Orientations, XYCell,
+    COLOR_CHARGING, COLOR_OBSTACLE, COLOR_PARKING, COLOR_ROAD, COLOR_TERRAIN,
+};
+
+/// One-way roads have the colours of the robots heading that way in `dpg-sim`
+pub const LEGEND_NORTH: Rgb<u8> = Rgb([255, 0, 0]);
+pub const LEGEND_SOUTH: Rgb<u8> = Rgb([0, 255, 0]);
+pub const LEGEND_WEST: Rgb<u8> = Rgb([255, 255, 0]);
+pub const LEGEND_EAST: Rgb<u8> = Rgb([0, 0, 255]);
+pub const LEGEND_NORTH_SOUTH: Rgb<u8> = Rgb([255, 0, 255]);
+pub const LEGEND_WEST_EAST: Rgb<u8> = Rgb([0, 255, 255]);
+/// Roads allowing all the orientations
+pub const LEGEND_ALL: Rgb<u8> = Rgb(COLOR_ROAD);
+
+/// The colour of a road allowing the orientations of the mask (see `Cell::orientation_mask`).
+/// The masks without a colour of their own are encoded as `[128, 16 * mask, 128]`.
+pub fn legend_color_from_mask(mask: u8) -> Rgb<u8> {
+    match mask {
+        0b0001 => LEGEND_NORTH,
+        0b0010 => LEGEND_SOUTH,
+        0b0100 => LEGEND_WEST,
+        0b1000 => LEGEND_EAST,
+        0b0011 => LEGEND_NORTH_SOUTH,
+        0b1100 => LEGEND_WEST_EAST,
+        0b1111 => LEGEND_ALL,
+        m => Rgb([128, 16 * m, 128]),
+    }
+}
+
+pub fn mask_from_legend_color(color: Rgb<u8>) -> Option<u8> {
+    match color.0 {
+        [128, g, 128] if g % 16 == 0 && g > 0 => Some(g / 16),
+        _ => (1..16).find(|m| legend_color_from_mask(*m) == color),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pixel {
+    Terrain,
+    Obstacle,
+    Road(u8),
+    Parking,
+    Charging,
+}
+
+fn pixel_from_color(color: Rgb<u8>) -> Option<Pixel> {
+    match color.0 {
+        COLOR_TERRAIN => Some(Pixel::Terrain),
+        COLOR_OBSTACLE => Some(Pixel::Obstacle),
+        COLOR_PARKING => Some(Pixel::Parking),
+        COLOR_CHARGING => Some(Pixel::Charging),
+        _ => mask_from_legend_color(color).map(Pixel::Road),
+    }
+}
+
+/// A spot is entered forward from the road behind it, which must allow the spot's facing.
+fn spot_facing(grid: &Grid, xy: XYCell) -> Result<Orientations, String> {
+    let facings = [
+        Orientations::NORTH,
+        Orientations::SOUTH,
+        Orientations::WEST,
+        Orientations::EAST,
+    ]
+    .into_iter()
+    .filter(|o| {
+        let behind = next_coords(&Coords::from(xy, *o), Actions::Backward).xy;
+        grid.size.in_bounds(behind) && {
+            let cell = grid.get_cell(&behind);
+            !cell.is_parking && !cell.is_charging && cell.is_allowed(*o)
+        }
+    })
+    .collect_vec();
+    match facings.as_slice() {
+        [o] => Ok(*o),
+        [] => Err(format!("no road leads to the spot at {xy:?}")),
+        _ => Err(format!("several roads lead to the spot at {xy:?}")),
+    }
+}
+
+/// Builds a grid from an image with one pixel per cell; the top row has the largest y.
+///
+/// The legend is:
+/// - `COLOR_TERRAIN` terrain and `COLOR_OBSTACLE` obstacles;
+/// - roads in the colours of `legend_color_from_mask`;
+/// - `COLOR_PARKING` parking spots and `COLOR_CHARGING` charging spots, facing away from
+///   the only road that leads to them.
+///
+/// Any other colour is an error.
+///
+/// The renders of `render_grid_legend`, such as the maps of `dpg-sim`, load back as the grid.
+pub fn grid_from_image(img: &RgbImage) -> Result<Grid, String> {
+    let size = XYCell::new(img.width() as i16, img.height() as i16);
+    let mut grid = Grid::new(size);
+    let mut spots = Vec::new();
+    for (u, v, color) in img.enumerate_pixels() {
+        let xy = XYCell::new(u as i16, size.y - v as i16 - 1);
+        let pixel = pixel_from_color(*color)
+            .ok_or(format!("unknown colour {:?} at pixel ({u}, {v})", color.0))?;
+        match pixel {
+            Pixel::Terrain => {}
+            Pixel::Obstacle => grid.replace_cell(&xy, Cell::obstacle()),
+            Pixel::Road(mask) => {
+                for o in orientations_from_mask(mask) {
+                    grid.draw_road(&Coords::from(xy, o));
+                }
+            }
+            Pixel::Parking | Pixel::Charging => spots.push((xy, pixel)),
+        }
+    }
+    // the spots once all the roads are known
+    for (xy, pixel) in spots {
+        let o = spot_facing(&grid, xy)?;
+        let cell = if pixel == Pixel::Parking {
+            Cell::parking(o)
+        } else {
+            Cell::charging(o)
+        };
+        grid.replace_cell(&xy, cell);
+    }
+    Ok(grid)
+}
+
+/// Renders the grid with the legend read by `grid_from_image`.
+pub fn render_grid_legend(grid: &Grid) -> RgbImage {
+    let mut img = RgbImage::new(grid.size.x as u32, grid.size.y as u32);
+    for (xy, cell) in grid.iterate_cells() {
+        let color = if cell.is_obstacle {
+            Rgb(COLOR_OBSTACLE)
+        } else if cell.is_parking {
+            Rgb(COLOR_PARKING)
+        } else if cell.is_charging {
+            Rgb(COLOR_CHARGING)
+        } else if cell.traversable() {
+            legend_color_from_mask(cell.orientation_mask())
+        } else {
+            Rgb(COLOR_TERRAIN)
+        };
+        img.put_pixel(xy.x as u32, (grid.size.y - xy.y - 1) as u32, color);
+    }
+    img
+}
+
+pub fn load_grid_png(filename: &str) -> Result<Grid, String> {
+    let img = image::open(filename).map_err(|e| format!("cannot read {filename}: {e}"))?;
+    grid_from_image(&img.to_rgb8())
+}
+
+pub fn save_grid_png(grid: &Grid, filename: &str) -> Result<(), String> {
+    render_grid_legend(grid)
+        .save(filename)
+        .map_err(|e| format!("cannot save {filename}: {e}"))
+}
+
+#[cfg(test)]
+mod test {
+    use image::Rgb;
+
+    use crate::*;
+
+    #[test]
+    fn test_legend_colors() {
+        for mask in 1..16 {
+            assert_eq!(
+                mask_from_legend_color(legend_color_from_mask(mask)),
+                Some(mask)
+            );
+        }
+        assert_eq!(mask_from_legend_color(Rgb([128, 0, 128])), None);
+    }
+
+    #[test]
+    fn test_png_round_trip() {
+        let mut rng = rng_from_seed(0);
+        let map_size = XYCell::new(3, 3);
+        let block_size = XYCell::new(16, 16);
+        let mut bl = BlockMap::new(map_size, block_size);
+        for p in map_size.iterate_xy_interior() {
+            bl.set_block(p, Block::with_parking(block_size, 1, &mut rng));
+        }
+        let grid = bl.stitch(&mut rng);
+        let loaded = grid_from_image(&render_grid_legend(&grid)).unwrap();
+        assert_eq!(format_grid(&loaded), format_grid(&grid));
+    }
+
+    #[test]
+    fn test_png_spot_facing() {
+        let grid = parse_grid(
+            "
+            .N..
+            >9a>
+            ..D.
+            ",
+        )
+        .unwrap();
+        let mut img = render_grid_legend(&grid);
+        assert_eq!(*img.get_pixel(1, 0), Rgb(COLOR_PARKING));
+        assert_eq!(*img.get_pixel(0, 1), LEGEND_EAST);
+        // the facings are inferred from the roads
+        let loaded = grid_from_image(&img).unwrap();
+        assert_eq!(format_grid(&loaded), format_grid(&grid));
+        // no road allows heading north into the spot
+        img.put_pixel(1, 1, LEGEND_WEST_EAST);
+        assert!(grid_from_image(&img).is_err());
+        img.put_pixel(3, 2, Rgb([1, 2, 3]));
+        assert!(grid_from_image(&img).is_err());
+    }
+
+    #[test]
+    fn test_sim_map_round_trip() {
+        // a map built as in dpg-sim, with empty blocks and charging spots
+        let mut rng = rng_from_seed(1);
+        let map_size = XYCell::new(4, 4);
+        let block_size = XYCell::new(16, 16);
+        let mut bl = BlockMap::new(map_size, block_size);
+        for p in map_size.iterate_xy_interior() {
+            if p != XYCell::new(1, 1) {
+                bl.set_block(p, Block::with_parking(block_size, 1, &mut rng));
+            }
+        }
+        let mut grid = bl.stitch(&mut rng);
+        let spots = grid.empty_parking_cells.queue.clone().into_sorted_vec();
+        assert!(!spots.is_empty());
+        for xy in spots.iter().step_by(10) {
+            let orientation = grid.get_cell(xy).random_direction(&mut rng);
+            grid.make_charging_cell(&Coords {
+                xy: *xy,
+                orientation,
+            });
+        }
+        let world = World::new(grid);
+        // the background of the frames of dpg-sim
+        let map = render_grid_legend(&world.grid);
+        let loaded = grid_from_image(&map).unwrap();
+        assert_eq!(format_grid(&loaded), format_grid(&world.grid));
+        // the one-way lanes keep their direction
+        assert!(world
+            .grid
+            .iterate_cells()
+            .any(|(_, cell)| cell.traversable() && cell.orientation_mask().count_ones() == 1));
+    }
+}
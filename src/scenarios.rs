@@ -0,0 +1,317 @@
+use itertools::Itertools;
+
+use crate::{simulate, Actions, ArbAgent, ArbSetup, Coords, Grid, Orientations, World, XYCell};
+
+const F: Actions = Actions::Forward;
+const L: Actions = Actions::TurnLeft;
+const R: Actions = Actions::TurnRight;
+const B: Actions = Actions::Backward;
+
+/// What an agent does at an intersection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Turn {
+    #[default]
+    Straight,
+    Left,
+    Right,
+}
+
+impl Turn {
+    /// The plan from the first cell before the intersection until out of it.
+    pub fn plan(&self) -> Vec<Actions> {
+        match self {
+            Turn::Straight => vec![F, F, F],
+            Turn::Left => vec![F, F, L, F, F],
+            Turn::Right => vec![F, R, F],
+        }
+    }
+
+    /// The side through which the agent coming from `side` leaves.
+    pub fn exit_side(&self, side: Orientations) -> Orientations {
+        // the agent coming from `side` heads the opposite way
+        let heading = side.rotate_left().rotate_left();
+        match self {
+            Turn::Straight => heading,
+            Turn::Left => heading.rotate_left(),
+            Turn::Right => heading.rotate_right(),
+        }
+    }
+}
+
+/// A queue of agents coming from one side of the intersection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Approach {
+    /// The side the agents come from
+    pub side: Orientations,
+    /// Number of agents in the queue
+    pub queue: usize,
+    pub turn: Turn,
+}
+
+impl Approach {
+    pub fn new(side: Orientations, queue: usize, turn: Turn) -> Self {
+        Self { side, queue, turn }
+    }
+
+    pub fn agents(&self) -> Vec<ArbAgent> {
+        (0..self.queue)
+            .map(|ord| approach(self.side, ord, self.turn))
+            .collect_vec()
+    }
+}
+
+/// The agent in position `ord` of the queue coming from `side` to the intersection
+/// made of the cells (-1, -1) to (0, 0), driving on the right.
+pub fn approach(side: Orientations, ord: usize, turn: Turn) -> ArbAgent {
+    let k = ord as i16;
+    let coord = match side {
+        Orientations::EAST => Coords::from(XYCell::new(1 + k, 0), Orientations::WEST),
+        Orientations::NORTH => Coords::from(XYCell::new(-1, 1 + k), Orientations::SOUTH),
+        Orientations::WEST => Coords::from(XYCell::new(-2 - k, -1), Orientations::EAST),
+        Orientations::SOUTH => Coords::from(XYCell::new(0, -2 - k), Orientations::NORTH),
+    };
+    let mut plan = vec![F; ord];
+    plan.extend(turn.plan());
+    ArbAgent::new(coord, plan)
+}
+
+/// A game from the library, with what is known about its solutions
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    pub name: String,
+    pub setup: ArbSetup,
+    /// A world with roads along the paths of the agents and the agents placed,
+    /// translated by `offset` so that all the coordinates are positive.
+    pub world: Option<World>,
+    pub offset: XYCell,
+    /// Number of Pareto solutions found by `find_feasible_plans`, if known
+    pub expected_solutions: Option<usize>,
+}
+
+impl Scenario {
+    pub fn new(name: &str, setup: ArbSetup) -> Self {
+        Self {
+            name: name.to_string(),
+            setup,
+            world: None,
+            offset: XYCell::new(0, 0),
+            expected_solutions: None,
+        }
+    }
+
+    pub fn with_expected(self, n: usize) -> Self {
+        Self {
+            expected_solutions: Some(n),
+            ..self
+        }
+    }
+
+    /// Builds the world for the scenario.
+    pub fn with_world(self) -> Self {
+        let (world, offset) = world_from_setup(&self.setup);
+        Self {
+            world: Some(world),
+            offset,
+            ..self
+        }
+    }
+}
+
+/// A world whose roads are the paths of the agents; parking spots where agents back out.
+/// Returns the world and the translation applied to the coordinates.
+pub fn world_from_setup(s: &ArbSetup) -> (World, XYCell) {
+    let paths = s
+        .agents
+        .iter()
+        .map(|a| simulate(a.coord, &a.plan))
+        .collect_vec();
+    let xys = paths.iter().flatten().map(|c| c.xy).collect_vec();
+    let min_x = xys.iter().map(|xy| xy.x).min().unwrap_or(0);
+    let min_y = xys.iter().map(|xy| xy.y).min().unwrap_or(0);
+    let max_x = xys.iter().map(|xy| xy.x).max().unwrap_or(0);
+    let max_y = xys.iter().map(|xy| xy.y).max().unwrap_or(0);
+    let offset = XYCell::new(1 - min_x, 1 - min_y);
+    let size = XYCell::new(max_x - min_x + 3, max_y - min_y + 3);
+    let mut grid = Grid::new(size);
+    let shift = |c: &Coords| Coords::from(c.xy + offset, c.orientation);
+    for (agent, path) in s.agents.iter().zip(paths.iter()) {
+        for c in path {
+            let c = shift(c);
+            if !grid.get_cell(&c.xy).is_parking {
+                grid.draw_road(&c);
+            }
+        }
+        if agent.plan.first() == Some(&B) {
+            grid.make_parking_cell(&shift(&agent.coord));
+        }
+    }
+    let mut world = World::new(grid);
+    for agent in &s.agents {
+        world.place_robot(shift(&agent.coord));
+    }
+    (world, offset)
+}
+
+/// Four-way crossing with the given queues.
+pub fn four_way(approaches: &[Approach]) -> Scenario {
+    let agents = approaches.iter().flat_map(|a| a.agents()).collect_vec();
+    Scenario::new("four-way", ArbSetup { agents })
+}
+
+/// T-junction: a road from east to west with a branch to the south.
+pub fn t_junction(approaches: &[Approach]) -> Scenario {
+    for a in approaches {
+        if a.side == Orientations::NORTH || a.turn.exit_side(a.side) == Orientations::NORTH {
+            panic!("t_junction: there is no road to the north: {a:?}");
+        }
+    }
+    let agents = approaches.iter().flat_map(|a| a.agents()).collect_vec();
+    Scenario::new("T-junction", ArbSetup { agents })
+}
+
+/// A ramp from the south merging into a westbound road at (0, 0).
+pub fn merge(main: usize, ramp: usize) -> Scenario {
+    let mut agents = (0..main)
+        .map(|k| {
+            ArbAgent::new(
+                Coords::from(XYCell::new(1 + k as i16, 0), Orientations::WEST),
+                vec![F; 3 + k],
+            )
+        })
+        .collect_vec();
+    for k in 0..ramp {
+        let mut plan = vec![F; k + 1];
+        plan.extend([L, F, F]);
+        agents.push(ArbAgent::new(
+            Coords::from(XYCell::new(0, -1 - k as i16), Orientations::NORTH),
+            plan,
+        ));
+    }
+    Scenario::new("merge", ArbSetup { agents })
+}
+
+/// A roundabout around the island (0, 0), turning counterclockwise.
+/// The `entering` agents come from the south and leave to the north;
+/// the `circulating` ones (at most 2) come down the west side and leave to the east.
+pub fn roundabout_entry(entering: usize, circulating: usize) -> Scenario {
+    if circulating > 2 {
+        panic!("roundabout_entry: at most 2 circulating agents");
+    }
+    let mut agents = Vec::new();
+    for k in 0..circulating {
+        let mut plan = vec![F; k];
+        plan.extend([F, L, F, F, L, F, R, F]);
+        agents.push(ArbAgent::new(
+            Coords::from(XYCell::new(-1, k as i16), Orientations::SOUTH),
+            plan,
+        ));
+    }
+    for k in 0..entering {
+        let mut plan = vec![F; k];
+        plan.extend([F, R, F, L, F, F, L, F, R, F]);
+        agents.push(ArbAgent::new(
+            Coords::from(XYCell::new(0, -2 - k as i16), Orientations::NORTH),
+            plan,
+        ));
+    }
+    Scenario::new("roundabout entry", ArbSetup { agents })
+}
+
+/// An agent backs out of the parking spot (0, 1) into an eastbound road along y = 0,
+/// where `traffic` agents are coming from the west.
+pub fn parking_exit(traffic: usize) -> Scenario {
+    let mut agents = vec![ArbAgent::new(
+        Coords::from(XYCell::new(0, 1), Orientations::NORTH),
+        vec![B, R, F, F],
+    )];
+    for k in 0..traffic {
+        agents.push(ArbAgent::new(
+            Coords::from(XYCell::new(-2 - k as i16, 0), Orientations::EAST),
+            vec![F; 4 + k],
+        ));
+    }
+    Scenario::new("parking exit", ArbSetup { agents })
+}
+
+/// The shared set of scenarios, with the number of solutions they are known to have.
+pub fn canonical_scenarios() -> Vec<Scenario> {
+    let all_sides = |queue, turn| {
+        [
+            Orientations::EAST,
+            Orientations::NORTH,
+            Orientations::WEST,
+            Orientations::SOUTH,
+        ]
+        .iter()
+        .map(|side| Approach::new(*side, queue, turn))
+        .collect_vec()
+    };
+    vec![
+        four_way(&all_sides(1, Turn::Straight)).with_expected(6),
+        four_way(&all_sides(1, Turn::Right)).with_expected(1),
+        four_way(&[
+            Approach::new(Orientations::EAST, 2, Turn::Straight),
+            Approach::new(Orientations::SOUTH, 1, Turn::Left),
+        ])
+        .with_expected(3),
+        t_junction(&[
+            Approach::new(Orientations::EAST, 1, Turn::Left),
+            Approach::new(Orientations::WEST, 1, Turn::Straight),
+            Approach::new(Orientations::SOUTH, 1, Turn::Right),
+        ])
+        .with_expected(2),
+        merge(2, 1).with_expected(3),
+        roundabout_entry(1, 1).with_expected(1),
+        parking_exit(1).with_expected(2),
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    #[test]
+    fn test_canonical_scenarios() {
+        for scenario in canonical_scenarios() {
+            let result = find_feasible_plans(&scenario.setup, 0);
+            assert_eq!(
+                Some(result.solutions.len()),
+                scenario.expected_solutions,
+                "{}",
+                scenario.name
+            );
+        }
+    }
+
+    #[test]
+    fn test_queues_extend_away_from_the_intersection() {
+        for side in [
+            Orientations::EAST,
+            Orientations::NORTH,
+            Orientations::WEST,
+            Orientations::SOUTH,
+        ] {
+            let first = approach(side, 0, Turn::Straight);
+            let second = approach(side, 1, Turn::Straight);
+            // the second one is right behind the first one
+            assert_eq!(
+                next_coords(&second.coord, Actions::Forward),
+                first.coord,
+                "{side:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_scenario_world() {
+        let scenario = parking_exit(2).with_world();
+        let world = scenario.world.as_ref().unwrap();
+        assert_eq!(world.robots.len(), 3);
+        for (robot, agent) in world.robots.iter().zip(scenario.setup.agents.iter()) {
+            assert_eq!(robot.xy(), agent.coord.xy + scenario.offset);
+            assert!(world.valid_coords(&robot.coords));
+        }
+        let spot = scenario.setup.agents[0].coord.xy + scenario.offset;
+        assert!(world.grid.get_cell(&spot).is_parking);
+    }
+}
@@ -0,0 +1,387 @@
+use std::collections::{HashMap, HashSet};
+
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::{next_coords, Actions, Coords, Grid, Orientations, Robot, XYCell, NUM_ACTIONS};
+
+/// The moves allowed at the stop lines of a signal while the phase lasts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignalPhase {
+    /// `actions[i][a]`: whether the robots on stop line `i` may take action `a`
+    pub actions: Vec<[bool; NUM_ACTIONS]>,
+    /// Number of steps of the phase under fixed-time control
+    pub duration: usize,
+}
+
+impl SignalPhase {
+    /// Green for all the actions on the given stop lines, red on the others
+    pub fn green(nstop_lines: usize, green: &[usize], duration: usize) -> Self {
+        let mut actions = vec![[false; NUM_ACTIONS]; nstop_lines];
+        for i in green {
+            actions[*i] = [true; NUM_ACTIONS];
+        }
+        Self { actions, duration }
+    }
+
+    /// Waiting is always allowed
+    pub fn allows(&self, stop_line: usize, action: Actions) -> bool {
+        action == Actions::Wait || self.actions[stop_line][action as usize]
+    }
+
+    fn is_green(&self, stop_line: usize) -> bool {
+        self.actions[stop_line][1..].iter().any(|a| *a)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SignalControl {
+    /// The phases follow each other, each for its `duration`
+    FixedTime,
+    /// Each phase lasts between `min_green` and `max_green` steps. After `min_green` it ends
+    /// as soon as no robot waits for it while some robot waits for another phase.
+    Actuated { min_green: usize, max_green: usize },
+}
+
+/// A signal controlling the robots on its stop lines, the coordinates from which they
+/// enter a junction.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Signal {
+    pub stop_lines: Vec<Coords>,
+    pub phases: Vec<SignalPhase>,
+    pub control: SignalControl,
+    /// The current phase
+    pub current: usize,
+    /// Number of steps already spent in the current phase
+    pub elapsed: usize,
+}
+
+impl Signal {
+    pub fn new(stop_lines: Vec<Coords>, phases: Vec<SignalPhase>, control: SignalControl) -> Self {
+        if phases.is_empty() {
+            panic!("A signal needs at least one phase");
+        }
+        for phase in phases.iter() {
+            assert_eq!(phase.actions.len(), stop_lines.len());
+            assert!(phase.duration > 0, "Phases last at least one step");
+        }
+        Self {
+            stop_lines,
+            phases,
+            control,
+            current: 0,
+            elapsed: 0,
+        }
+    }
+
+    /// The phase `dt` steps from now; actuated signals are assumed to keep the current one.
+    pub fn phase_in(&self, dt: usize) -> &SignalPhase {
+        match self.control {
+            SignalControl::FixedTime => {
+                let mut p = self.current;
+                let mut t = self.elapsed + dt;
+                while t >= self.phases[p].duration {
+                    t -= self.phases[p].duration;
+                    p = (p + 1) % self.phases.len();
+                }
+                &self.phases[p]
+            }
+            SignalControl::Actuated { .. } => &self.phases[self.current],
+        }
+    }
+
+    /// Moves to the next step, given the phases for which robots are waiting.
+    pub fn advance(&mut self, demand: &[bool]) {
+        self.elapsed += 1;
+        let next = match self.control {
+            SignalControl::FixedTime => {
+                if self.elapsed < self.phases[self.current].duration {
+                    return;
+                }
+                (self.current + 1) % self.phases.len()
+            }
+            SignalControl::Actuated {
+                min_green,
+                max_green,
+            } => {
+                if self.elapsed < min_green {
+                    return;
+                }
+                let n = self.phases.len();
+                let waiting = (1..n).map(|k| (self.current + k) % n).find(|p| demand[*p]);
+                match waiting {
+                    Some(p) if !demand[self.current] || self.elapsed >= max_green => p,
+                    _ => return,
+                }
+            }
+        };
+        self.current = next;
+        self.elapsed = 0;
+    }
+
+    /// For each phase, whether a robot waits on one of its green stop lines
+    pub fn demand(&self, occupied: &HashSet<Coords>) -> Vec<bool> {
+        self.phases
+            .iter()
+            .map(|phase| {
+                self.stop_lines
+                    .iter()
+                    .enumerate()
+                    .any(|(i, c)| phase.is_green(i) && occupied.contains(c))
+            })
+            .collect_vec()
+    }
+}
+
+/// The signals of a world, indexed by stop line
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Signals {
+    pub signals: Vec<Signal>,
+    index: HashMap<Coords, (usize, usize)>,
+}
+
+impl Signals {
+    pub fn add(&mut self, signal: Signal) -> usize {
+        let s = self.signals.len();
+        for (i, c) in signal.stop_lines.iter().enumerate() {
+            if self.index.insert(*c, (s, i)).is_some() {
+                panic!("Stop line {c:?} already has a signal");
+            }
+        }
+        self.signals.push(signal);
+        s
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.signals.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.signals.len()
+    }
+
+    /// Whether a robot at `coords` may take the action `dt` steps from now, as far as known
+    pub fn permits(&self, coords: &Coords, action: Actions, dt: usize) -> bool {
+        match self.index.get(coords) {
+            None => true,
+            Some((s, i)) => self.signals[*s].phase_in(dt).allows(*i, action),
+        }
+    }
+
+    /// Replaces with waits the plan from its first action stopped by a red signal.
+    /// Returns whether there was one.
+    pub fn restrict(&self, start: Coords, plan: &mut [Actions]) -> bool {
+        let mut coords = start;
+        let red = plan.iter().enumerate().position(|(dt, action)| {
+            let permitted = self.permits(&coords, *action, dt);
+            coords = next_coords(&coords, *action);
+            !permitted
+        });
+        match red {
+            Some(dt) => {
+                plan[dt..].fill(Actions::Wait);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn advance(&mut self, robots: &[Robot]) {
+        let occupied: HashSet<Coords> = robots.iter().map(|r| r.coords).collect();
+        for signal in self.signals.iter_mut() {
+            let demand = signal.demand(&occupied);
+            signal.advance(&demand);
+        }
+    }
+}
+
+/// Whether a robot at `from` facing `o` can move forward
+fn flows_forward(grid: &Grid, from: XYCell, o: Orientations) -> bool {
+    let to = from + o.vector();
+    grid.size.in_bounds(from)
+        && grid.size.in_bounds(to)
+        && grid.get_cell(&from).is_allowed(o)
+        && grid.get_cell(&from).ors[o as usize].action_allowed[Actions::Forward as usize]
+        && grid.get_cell(&to).is_allowed(o)
+}
+
+const ORIENTATIONS: [Orientations; 4] = [
+    Orientations::NORTH,
+    Orientations::SOUTH,
+    Orientations::WEST,
+    Orientations::EAST,
+];
+
+/// The stop lines of each junction: junctions are the connected groups of cells entered
+/// forward from more than one neighbour, and their stop lines the coordinates outside
+/// from which they are entered.
+pub fn junction_stop_lines(grid: &Grid) -> Vec<Vec<Coords>> {
+    let conflicts: HashSet<XYCell> = grid
+        .iterate_cells()
+        .map(|(xy, _)| xy)
+        .filter(|xy| {
+            ORIENTATIONS
+                .iter()
+                .filter(|o| flows_forward(grid, *xy - o.vector(), **o))
+                .count()
+                > 1
+        })
+        .collect();
+    let mut visited = HashSet::new();
+    let mut junctions = Vec::new();
+    for (start, _) in grid.iterate_cells() {
+        if !conflicts.contains(&start) || !visited.insert(start) {
+            continue;
+        }
+        let mut component = vec![start];
+        let mut todo = vec![start];
+        while let Some(xy) = todo.pop() {
+            for o in ORIENTATIONS {
+                let n = xy + o.vector();
+                if conflicts.contains(&n) && visited.insert(n) {
+                    component.push(n);
+                    todo.push(n);
+                }
+            }
+        }
+        let mut stop_lines = Vec::new();
+        for xy in component.iter() {
+            for o in ORIENTATIONS {
+                let from = *xy - o.vector();
+                if !component.contains(&from) && flows_forward(grid, from, o) {
+                    stop_lines.push(Coords::from(from, o));
+                }
+            }
+        }
+        stop_lines.sort_by_key(|c| (c.xy.x, c.xy.y, c.orientation as usize));
+        junctions.push(stop_lines);
+    }
+    junctions
+}
+
+/// A two-phase signal at each junction entered both along the north-south axis and along
+/// the west-east one: the first phase is green for the former, the second for the latter.
+pub fn junction_signals(grid: &Grid, control: SignalControl, green: usize) -> Vec<Signal> {
+    let mut signals = Vec::new();
+    for stop_lines in junction_stop_lines(grid) {
+        let (vertical, horizontal): (Vec<usize>, Vec<usize>) =
+            (0..stop_lines.len()).partition(|i| {
+                matches!(
+                    stop_lines[*i].orientation,
+                    Orientations::NORTH | Orientations::SOUTH
+                )
+            });
+        if vertical.is_empty() || horizontal.is_empty() {
+            continue;
+        }
+        let n = stop_lines.len();
+        let phases = vec![
+            SignalPhase::green(n, &vertical, green),
+            SignalPhase::green(n, &horizontal, green),
+        ];
+        signals.push(Signal::new(stop_lines, phases, control));
+    }
+    signals
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    /// Eastbound traffic merging with northbound traffic at (2, 1)
+    const MERGE: &str = "
+        ..^..
+        >>9>>
+        ..^..
+    ";
+
+    fn merge_world(control: SignalControl) -> World {
+        let grid = parse_grid(MERGE).unwrap();
+        let mut world = World::new(grid);
+        for signal in junction_signals(&world.grid, control, 3) {
+            world.signals.add(signal);
+        }
+        world
+    }
+
+    const EAST_STOP: Coords = Coords {
+        xy: XY { x: 1, y: 1 },
+        orientation: Orientations::EAST,
+    };
+    const NORTH_STOP: Coords = Coords {
+        xy: XY { x: 2, y: 0 },
+        orientation: Orientations::NORTH,
+    };
+
+    #[test]
+    fn test_junction_stop_lines() {
+        let grid = parse_grid(MERGE).unwrap();
+        assert_eq!(
+            junction_stop_lines(&grid),
+            vec![vec![EAST_STOP, NORTH_STOP]]
+        );
+        let signals = junction_signals(&grid, SignalControl::FixedTime, 3);
+        assert_eq!(signals.len(), 1);
+        // north-south first
+        assert!(signals[0].phases[0].allows(1, Actions::Forward));
+        assert!(!signals[0].phases[0].allows(0, Actions::Forward));
+    }
+
+    #[test]
+    fn test_fixed_time() {
+        let mut world = merge_world(SignalControl::FixedTime);
+        let red = world.allowed_robot_actions_if_empty(&EAST_STOP);
+        assert!(!red.contains(&Actions::Forward) && red.contains(&Actions::Wait));
+        assert!(world.signals.permits(&EAST_STOP, Actions::Forward, 3));
+        assert!(!world.signals.permits(&EAST_STOP, Actions::Forward, 6));
+        for _ in 0..3 {
+            world.signals.advance(&[]);
+        }
+        let green = world.allowed_robot_actions_if_empty(&EAST_STOP);
+        assert!(green.contains(&Actions::Forward));
+        assert!(!world.signals.permits(&NORTH_STOP, Actions::Forward, 0));
+    }
+
+    #[test]
+    fn test_actuated() {
+        let control = SignalControl::Actuated {
+            min_green: 2,
+            max_green: 4,
+        };
+        let mut world = merge_world(control);
+        world.place_robot(EAST_STOP);
+        // nobody waits for the north-south phase after its minimum
+        world.signals.advance(&world.robots);
+        assert_eq!(world.signals.signals[0].current, 0);
+        world.signals.advance(&world.robots);
+        assert_eq!(world.signals.signals[0].current, 1);
+        // it stays green while nobody else waits, then for at most `max_green`
+        for _ in 0..5 {
+            world.signals.advance(&world.robots);
+        }
+        assert_eq!(world.signals.signals[0].current, 1);
+        world.place_robot(NORTH_STOP);
+        world.signals.advance(&world.robots);
+        assert_eq!(world.signals.signals[0].current, 0);
+    }
+
+    #[test]
+    fn test_robots_stop_at_red() {
+        let mut world = merge_world(SignalControl::FixedTime);
+        world.place_robot(EAST_STOP);
+        let mut forward = |_: &mut RNG, _: usize, _: &Robot, horizon: usize| {
+            let mut plan = vec![Actions::Wait; horizon];
+            plan[0] = Actions::Forward;
+            plan
+        };
+        let mut rng = rng_from_seed(0);
+        let mut trajectory = Vec::new();
+        for _ in 0..4 {
+            world.step_robots(&mut forward, &mut rng);
+            trajectory.push(world.robots[0].coords.xy.x);
+        }
+        assert_eq!(trajectory, vec![1, 1, 1, 2]);
+        assert_eq!(world.stats.signal_stops, 3);
+        assert_eq!(world.signals.signals[0].current, 1);
+    }
+}
@@ -0,0 +1,266 @@
+use std::hash::Hash;
+
+use itertools::Itertools;
+
+use crate::{
+    assign, evaluate, get_permutations, le, ArbResult, ArbSetup, Cost, CostModel, WaitCount,
+};
+
+/// Priority class of a vehicle: the higher classes plan first (e.g. buses, emergency vehicles).
+pub type PriorityClass = u8;
+
+/// The classes present in the game, from the highest.
+pub fn priority_classes(s: &ArbSetup) -> Vec<PriorityClass> {
+    s.agents
+        .iter()
+        .map(|a| a.priority)
+        .unique()
+        .sorted_by_key(|c| std::cmp::Reverse(*c))
+        .collect_vec()
+}
+
+fn class_members(s: &ArbSetup, class: PriorityClass) -> Vec<usize> {
+    (0..s.agents.len())
+        .filter(|i| s.agents[*i].priority == class)
+        .collect_vec()
+}
+
+/// The orders where each class comes after all the higher ones, generated lazily:
+/// there are as many as the product of the factorials of the class sizes.
+pub fn iter_stackelberg_orders(s: &ArbSetup) -> impl Iterator<Item = Vec<usize>> {
+    let per_class = priority_classes(s)
+        .into_iter()
+        .map(|class| {
+            let members = class_members(s, class);
+            let k = members.len();
+            members.into_iter().permutations(k)
+        })
+        .collect_vec();
+    per_class
+        .into_iter()
+        .multi_cartesian_product()
+        .map(|parts| parts.concat())
+}
+
+/// The number of leader-first orders, if it fits in a `usize`
+pub fn count_stackelberg_orders(s: &ArbSetup) -> Option<usize> {
+    priority_classes(s)
+        .into_iter()
+        .try_fold(1usize, |acc, class| {
+            let k = class_members(s, class).len();
+            (1..=k).try_fold(acc, |f, i| f.checked_mul(i))
+        })
+}
+
+/// Whether each class of the order comes after all the higher ones
+pub fn is_stackelberg_order(s: &ArbSetup, order: &[usize]) -> bool {
+    order
+        .iter()
+        .map(|i| s.agents[*i].priority)
+        .tuple_windows()
+        .all(|(a, b)| a >= b)
+}
+
+/// The orders where each class comes after all the higher ones.
+/// At most `max` orders are returned (all of them if `max` is 0).
+pub fn stackelberg_orders(s: &ArbSetup, max: usize) -> Vec<Vec<usize>> {
+    let orders = iter_stackelberg_orders(s);
+    if max == 0 {
+        orders.collect_vec()
+    } else {
+        orders.take(max).collect_vec()
+    }
+}
+
+fn class_costs<V: Clone>(s: &ArbSetup, costs: &[V], class: PriorityClass) -> Vec<V> {
+    (0..s.agents.len())
+        .filter(|i| s.agents[*i].priority == class)
+        .map(|i| costs[i].clone())
+        .collect_vec()
+}
+
+/// Keeps the solutions that are best for the highest class, then among those
+/// the ones best for the next class, and so on.
+pub fn stackelberg_filter<V: Clone + Ord + Hash>(
+    s: &ArbSetup,
+    result: &ArbResult<V>,
+) -> ArbResult<V> {
+    let mut points = result.solutions.keys().cloned().collect_vec();
+    for class in priority_classes(s) {
+        let costs = points
+            .iter()
+            .map(|point| class_costs(s, point, class))
+            .collect_vec();
+        points = points
+            .into_iter()
+            .zip(costs.iter())
+            .filter(|(_, c)| !costs.iter().any(|other| le(other, c)))
+            .map(|(point, _)| point)
+            .collect_vec();
+    }
+    let mut res = ArbResult::empty();
+    for point in points {
+        let solutions = result.solutions[&point].clone();
+        res.solutions.insert(point, solutions);
+    }
+    res
+}
+
+/// Average total wait of the agents of the class, over the solutions of the result.
+pub fn mean_class_cost<V: Hash + Eq>(
+    s: &ArbSetup,
+    result: &ArbResult<V>,
+    class: PriorityClass,
+) -> Option<f64> {
+    let totals = result
+        .solutions
+        .values()
+        .flatten()
+        .map(|sol| class_costs(s, &sol.costs, class).iter().sum::<usize>())
+        .collect_vec();
+    if totals.is_empty() {
+        None
+    } else {
+        Some(totals.iter().sum::<usize>() as f64 / totals.len() as f64)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackelbergResult<V: Hash + Eq = Cost> {
+    /// The outcomes when the leaders plan first and the others best-respond
+    pub outcomes: ArbResult<V>,
+    /// The front when all the agents are treated the same
+    pub symmetric: ArbResult<V>,
+    /// How much less the leaders (the highest class) wait on average than in the symmetric front
+    pub leader_gain: f64,
+    /// Whether all the leader-first orders were evaluated
+    pub complete: bool,
+}
+
+/// Solves the game with the higher classes planning first.
+///
+/// `max` limits the number of orders evaluated in total, as in `find_feasible_plans`:
+/// the leader-first orders come first, and what is left of the budget goes to the other
+/// orders of the symmetric front, which also includes the leader-first solutions.
+pub fn find_stackelberg_plans(s: &ArbSetup, max: usize) -> StackelbergResult {
+    find_stackelberg_plans_with(s, max, WaitCount)
+}
+
+/// Like `find_stackelberg_plans`, with the fronts computed for the cost model.
+pub fn find_stackelberg_plans_with<M: CostModel>(
+    s: &ArbSetup,
+    max: usize,
+    model: M,
+) -> StackelbergResult<M::Value> {
+    let complete = max == 0 || count_stackelberg_orders(s).is_some_and(|n| n <= max);
+    let mut result = ArbResult::empty();
+    let mut symmetric = ArbResult::empty();
+    let mut evaluated = 0;
+    for order in iter_stackelberg_orders(s).take(if max == 0 { usize::MAX } else { max }) {
+        evaluated += 1;
+        if let Some((_, solution)) = assign(s, &order) {
+            let costs = evaluate(&model, s, &solution);
+            result.add(costs.clone(), &solution);
+            symmetric.add(costs, &solution);
+        }
+    }
+    if max == 0 || evaluated < max {
+        let left = if max == 0 { 0 } else { max - evaluated };
+        for order in get_permutations(s.agents.len(), left) {
+            if is_stackelberg_order(s, &order) {
+                continue;
+            }
+            if let Some((_, solution)) = assign(s, &order) {
+                symmetric.add(evaluate(&model, s, &solution), &solution);
+            }
+        }
+    }
+    let outcomes = stackelberg_filter(s, &result);
+    let leader_gain = match priority_classes(s).first() {
+        None => 0.0,
+        Some(leaders) => {
+            match (
+                mean_class_cost(s, &symmetric, *leaders),
+                mean_class_cost(s, &outcomes, *leaders),
+            ) {
+                (Some(before), Some(after)) => before - after,
+                _ => 0.0,
+            }
+        }
+    };
+    StackelbergResult {
+        outcomes,
+        symmetric,
+        leader_gain,
+        complete,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    const F: Actions = Actions::Forward;
+
+    fn crossing(bus_priority: PriorityClass) -> ArbSetup {
+        let agents = vec![
+            ArbAgent::new(
+                Coords::from(XYCell::new(1, 0), Orientations::WEST),
+                vec![F; 3],
+            ),
+            ArbAgent::new(
+                Coords::from(XYCell::new(0, -1), Orientations::NORTH),
+                vec![F; 3],
+            )
+            .with_priority(bus_priority),
+        ];
+        ArbSetup { agents }
+    }
+
+    #[test]
+    fn test_stackelberg_orders() {
+        let mut setup = crossing(1);
+        setup.agents.push(setup.agents[0].clone());
+        setup.agents[2].coord.xy = XYCell::new(2, 0);
+        let orders = stackelberg_orders(&setup, 0);
+        assert_eq!(orders, vec![vec![1, 0, 2], vec![1, 2, 0]]);
+        assert_eq!(stackelberg_orders(&setup, 1).len(), 1);
+        assert_eq!(count_stackelberg_orders(&setup), Some(2));
+        assert!(is_stackelberg_order(&setup, &[1, 2, 0]));
+        assert!(!is_stackelberg_order(&setup, &[0, 1, 2]));
+    }
+
+    #[test]
+    fn test_large_class_is_bounded() {
+        // 25! orders of a single class, more than a usize: only the first ones are generated
+        let agents = (0..25)
+            .map(|i| {
+                ArbAgent::new(
+                    Coords::from(XYCell::new(1 + i, 0), Orientations::WEST),
+                    vec![F; 3],
+                )
+            })
+            .collect();
+        let setup = ArbSetup { agents };
+        assert_eq!(count_stackelberg_orders(&setup), None);
+        assert_eq!(stackelberg_orders(&setup, 5).len(), 5);
+        let result = find_stackelberg_plans(&setup, 50);
+        assert!(!result.complete);
+    }
+
+    #[test]
+    fn test_leader_goes_first() {
+        let result = find_stackelberg_plans(&crossing(1), 0);
+        assert!(result.complete);
+        assert_eq!(result.outcomes.solutions.len(), 1);
+        let sol = result.outcomes.solutions.values().flatten().next().unwrap();
+        assert_eq!(sol.perm, vec![1, 0]);
+        assert_eq!(sol.costs[1], 0);
+        assert!(result.leader_gain > 0.0);
+
+        // with a single class this is the symmetric front
+        let result = find_stackelberg_plans(&crossing(0), 0);
+        assert_eq!(result.outcomes, result.symmetric);
+        assert_eq!(result.leader_gain, 0.0);
+    }
+}
@@ -0,0 +1,338 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Actions, ArbAgent, ArbResult, ArbSetup, ArbSolution, Coords, Orientations, PriorityClass,
+    RobotResult, XYCell,
+};
+
+/// One of the 8 symmetries of the grid: an optional mirror image followed by rotations
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GridSymmetry {
+    /// Mirror along the vertical axis (x -> -x) first
+    pub mirror: bool,
+    /// Then rotate counterclockwise this many times by 90 degrees
+    pub rotations: usize,
+}
+
+impl GridSymmetry {
+    pub fn all() -> Vec<Self> {
+        [false, true]
+            .iter()
+            .flat_map(|mirror| {
+                (0..4).map(move |rotations| GridSymmetry {
+                    mirror: *mirror,
+                    rotations,
+                })
+            })
+            .collect_vec()
+    }
+
+    pub fn apply_xy(&self, xy: XYCell) -> XYCell {
+        let mut xy = xy;
+        if self.mirror {
+            xy.x = -xy.x;
+        }
+        for _ in 0..self.rotations {
+            xy = XYCell::new(-xy.y, xy.x);
+        }
+        xy
+    }
+
+    pub fn apply_orientation(&self, orientation: Orientations) -> Orientations {
+        let mut orientation = match (self.mirror, orientation) {
+            (true, Orientations::EAST) => Orientations::WEST,
+            (true, Orientations::WEST) => Orientations::EAST,
+            (_, o) => o,
+        };
+        for _ in 0..self.rotations {
+            orientation = orientation.rotate_left();
+        }
+        orientation
+    }
+
+    /// Mirroring swaps the turns; rotations do not change the actions.
+    /// This is its own inverse.
+    pub fn apply_action(&self, action: Actions) -> Actions {
+        match (self.mirror, action) {
+            (true, Actions::TurnLeft) => Actions::TurnRight,
+            (true, Actions::TurnRight) => Actions::TurnLeft,
+            (_, a) => a,
+        }
+    }
+
+    pub fn apply_agent(&self, agent: &ArbAgent) -> ArbAgent {
+        ArbAgent {
+            coord: Coords::from(
+                self.apply_xy(agent.coord.xy),
+                self.apply_orientation(agent.coord.orientation),
+            ),
+            plan: agent
+                .plan
+                .iter()
+                .map(|a| self.apply_action(*a))
+                .collect_vec(),
+            end: agent.end,
+            priority: agent.priority,
+            vehicle: agent.vehicle,
+            trail: agent
+                .trail
+                .iter()
+                .map(|xy| self.apply_xy(*xy))
+                .collect_vec(),
+        }
+    }
+}
+
+/// A game brought into canonical form
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CanonicalGame {
+    pub setup: ArbSetup,
+    /// The symmetry that maps the original game to the canonical one (before translation)
+    pub symmetry: GridSymmetry,
+    /// For each canonical agent, the index of the original agent
+    pub order: Vec<usize>,
+}
+
+/// Sorting key of an agent: position, orientation, plan, end of plan, priority and body
+type AgentKey = (
+    i16,
+    i16,
+    usize,
+    Vec<usize>,
+    usize,
+    PriorityClass,
+    (u8, u8),
+    Vec<(i16, i16)>,
+);
+
+fn agent_key(agent: &ArbAgent) -> AgentKey {
+    let plan = agent.plan.iter().map(|a| *a as usize).collect_vec();
+    (
+        agent.coord.xy.x,
+        agent.coord.xy.y,
+        agent.coord.orientation as usize,
+        plan,
+        agent.end as usize,
+        agent.priority,
+        (agent.vehicle.length, agent.vehicle.max_speed),
+        agent.trail.iter().map(|xy| (xy.x, xy.y)).collect_vec(),
+    )
+}
+
+/// Translates the game to the origin, applies the symmetry and sorts the agents,
+/// choosing among the 8 symmetries the one giving the smallest description.
+pub fn canonicalize(s: &ArbSetup) -> CanonicalGame {
+    let mut best: Option<(Vec<AgentKey>, CanonicalGame)> = None;
+    for symmetry in GridSymmetry::all() {
+        let agents = s
+            .agents
+            .iter()
+            .map(|a| symmetry.apply_agent(a))
+            .collect_vec();
+        let min_x = agents.iter().map(|a| a.coord.xy.x).min().unwrap_or(0);
+        let min_y = agents.iter().map(|a| a.coord.xy.y).min().unwrap_or(0);
+        let origin = XYCell::new(min_x, min_y);
+        let agents = agents
+            .into_iter()
+            .map(|mut a| {
+                a.coord.xy = a.coord.xy - origin;
+                a.trail.iter_mut().for_each(|xy| *xy = *xy - origin);
+                a
+            })
+            .collect_vec();
+        let order = (0..agents.len())
+            .sorted_by_key(|i| agent_key(&agents[*i]))
+            .collect_vec();
+        let key = order.iter().map(|i| agent_key(&agents[*i])).collect_vec();
+        if best.as_ref().is_some_and(|(k, _)| *k <= key) {
+            continue;
+        }
+        let setup = ArbSetup {
+            agents: order.iter().map(|i| agents[*i].clone()).collect_vec(),
+        };
+        let game = CanonicalGame {
+            setup,
+            symmetry,
+            order,
+        };
+        best = Some((key, game));
+    }
+    best.unwrap().1
+}
+
+impl CanonicalGame {
+    fn solution_to_original(&self, sol: &ArbSolution) -> ArbSolution {
+        let n = self.order.len();
+        let mut costs = vec![0; n];
+        let mut robots = vec![
+            RobotResult {
+                plan: Vec::new(),
+                cost: 0,
+            };
+            n
+        ];
+        let mut payments = sol.payments.as_ref().map(|_| vec![0; n]);
+        for (k, original) in self.order.iter().enumerate() {
+            costs[*original] = sol.costs[k];
+            robots[*original] = RobotResult {
+                plan: sol.robots[k]
+                    .plan
+                    .iter()
+                    .map(|a| self.symmetry.apply_action(*a))
+                    .collect_vec(),
+                cost: sol.robots[k].cost,
+            };
+            if let (Some(p), Some(sp)) = (payments.as_mut(), sol.payments.as_ref()) {
+                p[*original] = sp[k];
+            }
+        }
+        ArbSolution {
+            perm: sol.perm.iter().map(|k| self.order[*k]).collect_vec(),
+            costs,
+            robots,
+            payments,
+        }
+    }
+
+    /// Maps a result of the canonical game back to the agents of the original game.
+    pub fn result_to_original<V: Clone + Hash + Eq>(&self, result: &ArbResult<V>) -> ArbResult<V> {
+        let mut solutions: HashMap<_, HashSet<ArbSolution>> = HashMap::new();
+        for (costs, equivalent) in &result.solutions {
+            let mut original = costs.clone();
+            for (k, i) in self.order.iter().enumerate() {
+                original[*i] = costs[k].clone();
+            }
+            let entry = solutions.entry(original).or_default();
+            entry.extend(equivalent.iter().map(|sol| self.solution_to_original(sol)));
+        }
+        ArbResult { solutions }
+    }
+}
+
+/// Results of solved games, stored by canonical form
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ArbCache {
+    results: HashMap<ArbSetup, ArbResult>,
+    pub hits: usize,
+    pub misses: usize,
+}
+
+impl ArbCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.results.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.results.is_empty()
+    }
+
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+
+    /// Returns the result for the game, calling `solve` on the canonical game if it was
+    /// not seen before. `solve` returns the result and whether it is complete; only complete
+    /// results are stored.
+    pub fn solve<F>(&mut self, s: &ArbSetup, solve: F) -> (ArbResult, bool)
+    where
+        F: FnOnce(&ArbSetup) -> (ArbResult, bool),
+    {
+        let game = canonicalize(s);
+        if let Some(result) = self.results.get(&game.setup) {
+            self.hits += 1;
+            return (game.result_to_original(result), true);
+        }
+        self.misses += 1;
+        let (result, complete) = solve(&game.setup);
+        let original = game.result_to_original(&result);
+        if complete {
+            self.results.insert(game.setup, result);
+        }
+        (original, complete)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use itertools::Itertools;
+
+    use crate::*;
+
+    const F: Actions = Actions::Forward;
+    const R: Actions = Actions::TurnRight;
+
+    fn agent(x: i16, y: i16, orientation: Orientations, plan: Vec<Actions>) -> ArbAgent {
+        ArbAgent::new(Coords::from(XYCell::new(x, y), orientation), plan)
+    }
+
+    #[test]
+    fn test_canonical_under_symmetry() {
+        let setup = ArbSetup {
+            agents: vec![
+                agent(1, 0, Orientations::WEST, vec![F, R, F]),
+                agent(0, -2, Orientations::NORTH, vec![F, F, F]),
+            ],
+        };
+        let canonical = canonicalize(&setup);
+        for symmetry in GridSymmetry::all() {
+            let mut agents = setup
+                .agents
+                .iter()
+                .map(|a| symmetry.apply_agent(a))
+                .collect_vec();
+            agents.reverse();
+            for a in agents.iter_mut() {
+                a.coord.xy = a.coord.xy + XYCell::new(10, 7);
+            }
+            let other = canonicalize(&ArbSetup { agents });
+            assert_eq!(other.setup, canonical.setup);
+        }
+    }
+
+    #[test]
+    fn test_cache_maps_back() {
+        let setup = ArbSetup {
+            agents: vec![
+                agent(1, 0, Orientations::WEST, vec![F, R, F]),
+                agent(0, -2, Orientations::NORTH, vec![F, F, F]),
+                agent(-2, -1, Orientations::EAST, vec![F, F, F]),
+            ],
+        };
+        let mirrored = ArbSetup {
+            agents: setup
+                .agents
+                .iter()
+                .rev()
+                .map(|a| {
+                    GridSymmetry {
+                        mirror: true,
+                        rotations: 1,
+                    }
+                    .apply_agent(a)
+                })
+                .collect_vec(),
+        };
+        let mut cache = ArbCache::new();
+        for s in [&setup, &mirrored, &setup] {
+            let (result, complete) = cache.solve(s, |c| (find_feasible_plans(c, 0), true));
+            assert!(complete);
+            assert_eq!(result, find_feasible_plans(s, 0));
+        }
+        assert_eq!(cache.hits, 2);
+        assert_eq!(cache.misses, 1);
+        assert_eq!(cache.len(), 1);
+    }
+}
@@ -0,0 +1,152 @@
+use itertools::Itertools;
+
+use crate::{
+    find_feasible_plans, sample_from_hashset, ArbResult, ArbSetup, ArbSolution, Cost, Costs, RNG,
+};
+
+/// Money transferred by an agent; positive means the agent pays.
+pub type Payment = i64;
+
+pub fn social_cost(costs: &Costs) -> Cost {
+    costs.iter().sum()
+}
+
+/// The smallest total cost over all the solutions found, if any.
+pub fn min_social_cost(result: &ArbResult) -> Option<Cost> {
+    result.solutions.keys().map(social_cost).min()
+}
+
+/// A solution of smallest total cost, the outcome at which the Clarke pivot payments
+/// are non-negative; ties are drawn at random.
+pub fn pick_efficient(result: &ArbResult, rng: &mut RNG) -> ArbSolution {
+    let best = min_social_cost(result).expect("pick_efficient: no solutions");
+    let efficient = result
+        .solutions
+        .iter()
+        .filter(|(costs, _)| social_cost(costs) == best)
+        .flat_map(|(_, equivalent)| equivalent.iter().cloned())
+        .collect();
+    sample_from_hashset(&efficient, rng)
+}
+
+/// The same game with agent `i` removed.
+pub fn without_agent(s: &ArbSetup, i: usize) -> ArbSetup {
+    let agents = s
+        .agents
+        .iter()
+        .enumerate()
+        .filter(|(j, _)| *j != i)
+        .map(|(_, a)| a.clone())
+        .collect_vec();
+    ArbSetup { agents }
+}
+
+/// For each agent, the best total cost the others could achieve if the agent
+/// was not there. None if the game without the agent has no solution.
+pub fn costs_without_each_agent(s: &ArbSetup, max: usize) -> Vec<Option<Cost>> {
+    (0..s.agents.len())
+        .map(|i| min_social_cost(&find_feasible_plans(&without_agent(s, i), max)))
+        .collect_vec()
+}
+
+/// Clarke pivot payments for the outcome with the given costs:
+/// each agent pays the increase in the others' total cost caused by its presence.
+pub fn payments_from_costs(costs: &Costs, without: &[Option<Cost>]) -> Vec<Payment> {
+    let total = social_cost(costs);
+    costs
+        .iter()
+        .zip(without.iter())
+        .map(|(own, others_alone)| match others_alone {
+            None => 0,
+            Some(c) => (total - own) as Payment - *c as Payment,
+        })
+        .collect_vec()
+}
+
+/// VCG payments for an outcome of the game, re-solving the game once per agent.
+pub fn vcg_payments(s: &ArbSetup, costs: &Costs, max: usize) -> Vec<Payment> {
+    let without = costs_without_each_agent(s, max);
+    payments_from_costs(costs, &without)
+}
+
+/// Like `find_feasible_plans`, but every solution also carries its VCG payments.
+pub fn find_feasible_plans_vcg(s: &ArbSetup, max: usize) -> ArbResult {
+    let result = find_feasible_plans(s, max);
+    let without = costs_without_each_agent(s, max);
+
+    let solutions = result
+        .solutions
+        .into_iter()
+        .map(|(costs, equivalent)| {
+            let payments = payments_from_costs(&costs, &without);
+            let equivalent = equivalent
+                .into_iter()
+                .map(|mut sol| {
+                    sol.payments = Some(payments.clone());
+                    sol
+                })
+                .collect();
+            (costs, equivalent)
+        })
+        .collect();
+    ArbResult { solutions }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::*;
+
+    fn queue_west(n: usize, nsteps: usize) -> ArbSetup {
+        let agents = (0..n)
+            .map(|i| {
+                ArbAgent::new(
+                    Coords::from(XYCell::new(1 + (i as i16), 0), Orientations::WEST),
+                    vec![Actions::Forward; nsteps],
+                )
+            })
+            .collect();
+        ArbSetup { agents }
+    }
+
+    #[test]
+    fn test_vcg_single_agent_pays_nothing() {
+        let setup = queue_west(1, 3);
+        let result = find_feasible_plans_vcg(&setup, 0);
+        for equivalent in result.solutions.values() {
+            for sol in equivalent {
+                assert_eq!(sol.payments, Some(vec![0]));
+            }
+        }
+    }
+
+    #[test]
+    fn test_vcg_payments_nonnegative_at_optimum() {
+        let setup = queue_west(3, 3);
+        let result = find_feasible_plans_vcg(&setup, 0);
+        let best = min_social_cost(&result).unwrap();
+        for (costs, equivalent) in result.solutions.iter() {
+            if social_cost(costs) != best {
+                continue;
+            }
+            for sol in equivalent {
+                let payments = sol.payments.as_ref().unwrap();
+                assert_eq!(payments.len(), 3);
+                assert!(payments.iter().all(|p| *p >= 0));
+            }
+        }
+    }
+
+    #[test]
+    fn test_pick_efficient() {
+        // a queue of three crossing one agent: letting the single one go first is cheapest
+        let mut agents = Approach::new(Orientations::EAST, 3, Turn::Straight).agents();
+        agents.push(approach(Orientations::NORTH, 0, Turn::Straight));
+        let result = find_feasible_plans(&ArbSetup { agents }, 0);
+        let best = min_social_cost(&result).unwrap();
+        assert!(result.solutions.keys().any(|c| social_cost(c) > best));
+        let mut rng = rng_from_seed(0);
+        for _ in 0..10 {
+            assert_eq!(social_cost(&pick_efficient(&result, &mut rng).costs), best);
+        }
+    }
+}
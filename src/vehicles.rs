@@ -0,0 +1,260 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{next_coords, Actions, Coords, XYCell};
+
+/// The size and speed of a robot.
+///
+/// The body is the head cell followed by its trail of `length - 1` cells, the ones the head
+/// last left. Moving forward drags the trail along, turns in place keep it, moving backward
+/// pushes it back. On parking and charging spots the whole body is folded into the spot:
+/// the trail repeats the cell of the spot, which stays covered until the tail has left it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Vehicle {
+    /// Number of cells covered by the body
+    pub length: u8,
+    /// Number of cells it can move forward in one step
+    pub max_speed: u8,
+}
+
+impl Default for Vehicle {
+    fn default() -> Self {
+        Vehicle::CAR
+    }
+}
+
+impl Vehicle {
+    pub const CAR: Vehicle = Vehicle {
+        length: 1,
+        max_speed: 1,
+    };
+    pub const SCOOTER: Vehicle = Vehicle {
+        length: 1,
+        max_speed: 2,
+    };
+    pub const BUS: Vehicle = Vehicle {
+        length: 3,
+        max_speed: 1,
+    };
+
+    pub fn new(length: u8, max_speed: u8) -> Self {
+        if length == 0 || max_speed == 0 {
+            panic!("Invalid vehicle of length {length} and speed {max_speed}");
+        }
+        Self { length, max_speed }
+    }
+
+    /// "car", "scooter" or "bus"
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "car" => Some(Vehicle::CAR),
+            "scooter" => Some(Vehicle::SCOOTER),
+            "bus" => Some(Vehicle::BUS),
+            _ => None,
+        }
+    }
+
+    /// One cell, one cell per step: what the exact search and the analyses assume
+    pub fn is_unit(&self) -> bool {
+        *self == Vehicle::CAR
+    }
+
+    /// The trail after the head at `coord` took the action
+    pub fn next_trail(&self, coord: &Coords, trail: &[XYCell], action: Actions) -> Vec<XYCell> {
+        let mut trail = trail.to_vec();
+        match action {
+            Actions::Forward => trail.insert(0, coord.xy),
+            Actions::Backward if trail.first() == Some(&next_coords(coord, action).xy) => {
+                // the tail goes on away from the rest of the body, unless it is folded
+                let n = trail.len();
+                let last = trail[n - 1];
+                let before = if n > 1 { trail[n - 2] } else { coord.xy };
+                trail.remove(0);
+                trail.push(last + last - before);
+            }
+            // backing off the trail drags it along
+            Actions::Backward => trail.insert(0, coord.xy),
+            _ => {}
+        }
+        trail.truncate(self.length as usize - 1);
+        trail
+    }
+
+    /// The head and the trail after the actions
+    pub fn after(
+        &self,
+        coord: &Coords,
+        trail: &[XYCell],
+        actions: &[Actions],
+    ) -> (Coords, Vec<XYCell>) {
+        let mut coord = *coord;
+        let mut trail = trail.to_vec();
+        for action in actions {
+            trail = self.next_trail(&coord, &trail, *action);
+            coord = next_coords(&coord, *action);
+        }
+        (coord, trail)
+    }
+
+    /// Splits a plan into steps: up to `max_speed` forward moves in a row make one step,
+    /// every other action is a step of its own. A step is done whole or not at all.
+    pub fn plan_steps<'a>(&self, plan: &'a [Actions]) -> Vec<&'a [Actions]> {
+        let mut steps = Vec::new();
+        let mut i = 0;
+        while i < plan.len() {
+            let run = plan[i..]
+                .iter()
+                .take(self.max_speed as usize)
+                .take_while(|a| **a == Actions::Forward)
+                .count()
+                .max(1);
+            steps.push(&plan[i..i + run]);
+            i += run;
+        }
+        steps
+    }
+}
+
+/// The cells covered by a body: the head, then the trail, each cell once
+pub fn body_cells(coord: &Coords, trail: &[XYCell]) -> Vec<XYCell> {
+    let mut cells = Vec::with_capacity(trail.len() + 1);
+    cells.push(coord.xy);
+    for xy in trail {
+        if !cells.contains(xy) {
+            cells.push(*xy);
+        }
+    }
+    cells
+}
+
+#[cfg(test)]
+mod test {
+    use itertools::Itertools;
+
+    use crate::*;
+
+    const F: Actions = Actions::Forward;
+    const W: Actions = Actions::Wait;
+    const L: Actions = Actions::TurnLeft;
+    const R: Actions = Actions::TurnRight;
+    const B: Actions = Actions::Backward;
+
+    #[test]
+    fn test_plan_steps() {
+        let plan = [F, F, F, L, F, W, F];
+        assert_eq!(Vehicle::CAR.plan_steps(&plan).len(), plan.len());
+        let steps = Vehicle::SCOOTER.plan_steps(&plan);
+        assert_eq!(
+            steps,
+            vec![
+                &[F, F][..],
+                &[F][..],
+                &[L][..],
+                &[F][..],
+                &[W][..],
+                &[F][..]
+            ]
+        );
+        assert!(Vehicle::SCOOTER.plan_steps(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_trail() {
+        let bus = Vehicle::BUS;
+        let start = Coords::from(XYCell::new(0, 0), Orientations::EAST);
+        let (coord, mut trail) = bus.after(&start, &[], &[F, F, F, L, F]);
+        // around the corner
+        assert_eq!(coord.xy, XYCell::new(3, 1));
+        assert_eq!(trail, vec![XYCell::new(3, 0), XYCell::new(2, 0)]);
+        assert_eq!(body_cells(&coord, &trail).len(), 3);
+        // reversing pushes the tail back
+        trail = bus.next_trail(&coord, &trail, B);
+        assert_eq!(trail, vec![XYCell::new(2, 0), XYCell::new(1, 0)]);
+        assert!(Vehicle::CAR.next_trail(&coord, &[], F).is_empty());
+
+        // backing out of a spot, the tail leaves it last
+        let spot = Coords::from(XYCell::new(1, 2), Orientations::NORTH);
+        let folded = vec![spot.xy; 2];
+        let (coord, trail) = bus.after(&spot, &folded, &[B, R, F]);
+        assert_eq!(
+            body_cells(&coord, &trail),
+            vec![XYCell::new(2, 1), XYCell::new(1, 1), spot.xy]
+        );
+        let (coord, trail) = bus.after(&coord, &trail, &[F]);
+        assert!(!body_cells(&coord, &trail).contains(&spot.xy));
+    }
+
+    #[test]
+    fn test_bus_and_scooter() {
+        let grid = parse_grid(">>>>>>>>>>>>>>>>").unwrap();
+        let mut world = World::new(grid);
+        let scooter = world.place_vehicle(
+            Coords::from(XY::new(0, 0), Orientations::EAST),
+            Vehicle::SCOOTER,
+        );
+        // the whole body is placed behind the head
+        let bus = world.place_vehicle(
+            Coords::from(XY::new(5, 0), Orientations::EAST),
+            Vehicle::BUS,
+        );
+        assert_eq!(world.grid.get_cell(&XY::new(3, 0)).present, Some(bus));
+        let mut f = |_: &mut RNG, _: usize, _: &Robot, horizon: usize| vec![F; horizon];
+        let mut rng = rng_from_seed(0);
+
+        world.step_robots(&mut f, &mut rng);
+        assert_eq!(world.robots[scooter].xy(), XY::new(2, 0));
+        assert_eq!(
+            world.robots[bus].body(),
+            vec![XY::new(6, 0), XY::new(5, 0), XY::new(4, 0)]
+        );
+
+        // the scooter cannot jump into the tail of the bus
+        world.step_robots(&mut f, &mut rng);
+        assert_eq!(world.robots[scooter].xy(), XY::new(2, 0));
+        assert_eq!(
+            world.robots[bus].body(),
+            vec![XY::new(7, 0), XY::new(6, 0), XY::new(5, 0)]
+        );
+        assert_eq!(world.grid.get_cell(&XY::new(5, 0)).present, Some(bus));
+        assert_eq!(world.grid.get_cell(&XY::new(4, 0)).present, None);
+    }
+
+    #[test]
+    fn test_bus_in_spot() {
+        let grid = parse_grid(
+            "
+            .N...
+            >+>>>
+            ",
+        )
+        .unwrap();
+        let mut world = World::new(grid);
+        let spot = Coords::from(XY::new(1, 1), Orientations::NORTH);
+        let bus = world.place_vehicle(spot, Vehicle::BUS);
+        // folded into the spot, with a full trail
+        assert_eq!(world.robots[bus].body(), vec![spot.xy]);
+        assert_eq!(world.robots[bus].trail.len(), 2);
+        // one action of the plan executed per step
+        let plan = [B, R, F, F, F];
+        let mut t = 0;
+        let mut f = |_: &mut RNG, _: usize, _: &Robot, horizon: usize| {
+            let p = (t..t + horizon)
+                .map(|k| plan.get(k).copied().unwrap_or(W))
+                .collect_vec();
+            t += 1;
+            p
+        };
+        let mut rng = rng_from_seed(0);
+        for _ in 0..3 {
+            world.step_robots(&mut f, &mut rng);
+        }
+        // still leaving the spot
+        assert_eq!(
+            world.robots[bus].body(),
+            vec![XY::new(2, 0), XY::new(1, 0), spot.xy]
+        );
+        assert_eq!(world.grid.get_cell(&spot.xy).present, Some(bus));
+        world.step_robots(&mut f, &mut rng);
+        assert_eq!(world.robots[bus].body().len(), 3);
+        assert_eq!(world.grid.get_cell(&spot.xy).present, None);
+    }
+}